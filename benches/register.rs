@@ -0,0 +1,42 @@
+// Compare applying a tuple gate through its full Kronecker product against the tensor-axis kernel
+// Run with `cargo bench`
+
+extern crate funqy;
+
+use funqy::engine::*;
+use std::time::Instant;
+
+fn had() -> Gate {
+	let n = Cf::new(1.0 / 2_f64.sqrt(), 0.0);
+	vec![vec![n, n], vec![n, -n]]
+}
+
+fn millis(start: Instant) -> f64 {
+	let d = start.elapsed();
+	d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1_000_000.0
+}
+
+fn main() {
+	for &n in &[8, 10, 12, 14, 16] {
+		let dims = vec![2; n];
+		let gates = vec![had(); n];
+		let state = get_state(0);
+
+		let start = Instant::now();
+		let axes = apply_axes(state.clone(), &dims, &gates);
+		let axes_time = millis(start);
+
+		// The dense product takes 16 * 4^n bytes, so stop comparing past 12 qubits
+		if n <= 12 {
+			let start = Instant::now();
+			let full = gates.iter().cloned().fold(vec![get_state(0)], |a, b| a.combine(b));
+			let full = state.pad(full.len()).extract(full);
+			let full_time = millis(start);
+			assert!(full.iter().zip(axes.iter()).all(|(a, b)| (a - b).norm() < EPSILON));
+			println!("{:>2} qubits: kronecker {:>10.3} ms, axes {:>8.3} ms", n, full_time, axes_time);
+		}
+		else {
+			println!("{:>2} qubits: kronecker {:>10} ms, axes {:>8.3} ms", n, "-", axes_time);
+		}
+	}
+}
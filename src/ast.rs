@@ -1,4 +1,4 @@
-use engine::Phase;
+use engine::{Phase, Real};
 
 use std::rc::Rc;
 
@@ -22,16 +22,26 @@ pub enum Decl {
 	Let(Pat, Exp),
 	Type(Ident, Pat),
 	Data(Ident, Vec<Ident>),
-	Assert(Exp, Exp),
+	Assert(Exp, Exp, Compare, Option<Real>),
 	Print(Exp),
 	Do(Exp),
 }
 
+// Comparison used by an assertion
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Compare {
+	Equal,
+	// Equal up to a global phase
+	Similar,
+}
+
 // Expression
 type ExpRc = Rc<Exp>;
 #[derive(Clone,Debug,PartialEq)]
 pub enum Exp {
 	Index(usize),
+	Real(Real),
+	Complex(Phase),
 	String(String),
 	Var(Ident),
 	Scope(Vec<Decl>, ExpRc),
@@ -43,7 +53,9 @@ pub enum Exp {
 	Invoke(ExpRc, ExpRc),
 	Repeat(usize, ExpRc),
 	State(ExpRc),
-	Phase(Phase, ExpRc),
+	// Ket scaled by an amplitude, such as `0.6|F⟩`
+	Scale(ExpRc, ExpRc),
+	Phase(ExpRc, ExpRc),
 	Extract(ExpRc, Vec<Case>),
 	Anno(ExpRc, Pat),
 }
@@ -0,0 +1,476 @@
+use error::*;
+use engine::*;
+use types::*;
+use eval::*;
+use eval_static::either_type;
+use stabilizer::*;
+use sparse::*;
+
+use std::fmt;
+use std::rc::Rc;
+
+// Simulator used to evaluate state expressions
+pub trait Backend {
+	fn name(&self) -> &str;
+
+	// State for a basis value, tuple of values or existing state
+	fn prepare(&self, val: RunVal) -> Ret<RunVal>;
+
+	// Apply a gate, typing the result as `ty`
+	fn apply(&self, val: RunVal, gate: Op, ty: Type, config: &Config) -> Ret<RunVal>;
+
+	// Apply each gate to the matching component of a tuple-shaped state
+	fn apply_tuple(&self, val: RunVal, ops: Vec<Op>, ty: Type, config: &Config) -> Ret<RunVal>;
+
+	// Apply a channel given by its Kraus operators
+	fn apply_channel(&self, val: RunVal, kraus: &Channel, ty: Type) -> Ret<RunVal>;
+
+	// Equal superposition of the given values
+	fn sup(&self, vals: Vec<RunVal>) -> Ret<RunVal>;
+
+	fn phase(&self, val: RunVal, phase: Phase) -> Ret<RunVal>;
+
+	// Sample an outcome of the same type as the state (or decohere it in density mode)
+	fn measure(&self, val: RunVal, config: &Config) -> Ret<RunVal>;
+
+	// Measure component `k` of a tuple-typed state as `(outcome, remaining state)`
+	fn measure_part(&self, val: RunVal, k: usize, config: &Config) -> Ret<RunVal>;
+
+	// Probability of each basis outcome, along with the type of the outcomes
+	fn probs(&self, val: RunVal) -> Ret<(Vec<Real>, Type)>;
+}
+
+// Default simulator: matrix product states and stabilizer tableaus when enabled, and state vectors otherwise
+pub fn default_backend() -> BackendRef {
+	BackendRef::new(MpsBackend(StabilizerBackend(DenseBackend)))
+}
+
+// State vector simulator, storing large mostly-zero states sparsely and falling back to density matrices for mixed states
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct DenseBackend;
+
+impl Backend for DenseBackend {
+	fn name(&self) -> &str {
+		"dense"
+	}
+
+	fn prepare(&self, val: RunVal) -> Ret<RunVal> {
+		match val {
+			val @ RunVal::Density(_, _) => Ok(val),
+			val => match build_sparse(&val) {
+				Some((s, t)) => Ok(choose_state(s, t)),
+				None => {
+					let (s, t) = build_state_typed(val)?;
+					Ok(RunVal::State(s, t))
+				},
+			},
+		}
+	}
+
+	fn apply(&self, val: RunVal, gate: Op, ty: Type, _config: &Config) -> Ret<RunVal> {
+		if let Some(s) = sparse_input(&val) {
+			return Ok(choose_state(s.extract(gate), ty))
+		}
+		if is_mixed(&val) {
+			let (rho, _) = build_density_typed(val)?;
+			Ok(RunVal::Density(rho.extract(gate), ty))
+		}
+		else {
+			let (s, _) = build_state_typed(val)?;
+			Ok(RunVal::State(s.extract(gate), ty))
+		}
+	}
+
+	fn apply_tuple(&self, val: RunVal, ops: Vec<Op>, ty: Type, config: &Config) -> Ret<RunVal> {
+		if let Some(s) = apply_axes_sparse(&ops, &val) {
+			return Ok(choose_state(s, ty))
+		}
+		if let Some(s) = apply_axes(&ops, &val) {
+			return Ok(RunVal::State(s, ty))
+		}
+		let gate = ops.into_iter().fold(Op::Diag(get_state(0)), Op::combine);
+		self.apply(val, gate, ty, config)
+	}
+
+	fn apply_channel(&self, val: RunVal, kraus: &Channel, ty: Type) -> Ret<RunVal> {
+		let (rho, _) = build_density_typed(val)?;
+		Ok(RunVal::Density(rho.apply_channel(kraus)?, ty))
+	}
+
+	fn sup(&self, vals: Vec<RunVal>) -> Ret<RunVal> {
+		// Values of the same type keep it in superposition
+		let t = vals.iter().map(get_val_type).fold(None, |a, b| Some(a.map_or(b.clone(), |a| either_type(a, b)))).unwrap_or(Type::Any);
+		if vals.iter().any(is_mixed) {
+			// Mixed states have no coherent superposition, so combine them as an equal-weight ensemble
+			let rhos = vals.into_iter().map(build_density_typed).collect::<Ret<Vec<_>>>()?;
+			return Ok(RunVal::Density(Density::mix(rhos.into_iter().map(|(rho, _)| (1.0, rho)).collect()), t))
+		}
+		if let Some(states) = vals.iter().map(sparse_input).collect::<Option<Vec<_>>>() {
+			return Ok(choose_state(create_sparse_sup(states), t))
+		}
+		let states = vals.into_iter().map(|val| build_state_typed(val).map(|(s, _)| s)).collect::<Ret<Vec<_>>>()?;
+		Ok(RunVal::State(create_sup(states), t))
+	}
+
+	fn phase(&self, val: RunVal, phase: Phase) -> Ret<RunVal> {
+		match val {
+			RunVal::Density(rho, t) => Ok(RunVal::Density(rho.phase(phase), t)),
+			RunVal::Sparse(s, t) => Ok(RunVal::Sparse(s.phase(phase), t)),
+			val => {
+				let (s, t) = build_state_typed(val)?;
+				Ok(RunVal::State(s.phase(phase), t))
+			},
+		}
+	}
+
+	fn measure(&self, val: RunVal, config: &Config) -> Ret<RunVal> {
+		if config.density {
+			let (rho, t) = build_density_typed(val)?;
+			Ok(RunVal::Density(rho.decohere(), t))
+		}
+		else if is_mixed(&val) {
+			let (rho, t) = build_density_typed(val)?;
+			t.assign(RunVal::Index(rho.measure(&mut *config.rng.get())))
+		}
+		else if let Some((s, t)) = build_sparse(&val) {
+			t.assign(RunVal::Index(s.measure(&mut *config.rng.get())))
+		}
+		else {
+			let (s, t) = build_state_typed(val)?;
+			t.assign(RunVal::Index(s.measure(&mut *config.rng.get())))
+		}
+	}
+
+	fn measure_part(&self, val: RunVal, k: usize, config: &Config) -> Ret<RunVal> {
+		if config.density || is_mixed(&val) {
+			let (rho, t) = build_density_typed(val)?;
+			let (dims, part_type, rest_type) = split_tuple_type(&t, k, rho.size())?;
+			let rho = rho.pad(dims.iter().product());
+			if config.density {
+				return Ok(RunVal::Density(decohere_part(&rho, &dims, k), Type::Tuple(vec![part_type, rest_type])))
+			}
+			let m = sample_index(&part_probs(&rho.probs(), &dims, k), &mut *config.rng.get());
+			Ok(RunVal::Tuple(vec![outcome_val(&part_type, m), RunVal::Density(collapse_density_part(&rho, &dims, k, m), rest_type)]))
+		}
+		else {
+			let (s, t) = build_state_typed(val)?;
+			let (dims, part_type, rest_type) = split_tuple_type(&t, k, s.len())?;
+			let s = s.pad(dims.iter().product());
+			let m = sample_index(&part_probs(&s.probs(), &dims, k), &mut *config.rng.get());
+			Ok(RunVal::Tuple(vec![outcome_val(&part_type, m), RunVal::State(collapse_state_part(&s, &dims, k, m), rest_type)]))
+		}
+	}
+
+	fn probs(&self, val: RunVal) -> Ret<(Vec<Real>, Type)> {
+		if is_mixed(&val) {
+			let (rho, t) = build_density_typed(val)?;
+			Ok((rho.probs(), t))
+		}
+		else {
+			let (s, t) = build_state_typed(val)?;
+			Ok((s.probs(), t))
+		}
+	}
+}
+
+// Simulator running Clifford gates on a stabilizer tableau (see `StabilizerMode`), and other operations on the given backend
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct StabilizerBackend<B>(pub B);
+
+impl<B: Backend> Backend for StabilizerBackend<B> {
+	fn name(&self) -> &str {
+		"stabilizer"
+	}
+
+	fn prepare(&self, val: RunVal) -> Ret<RunVal> {
+		self.0.prepare(val)
+	}
+
+	fn apply(&self, val: RunVal, gate: Op, ty: Type, config: &Config) -> Ret<RunVal> {
+		match apply_stabilizer(::std::slice::from_ref(&gate), &val, config) {
+			Some(result) => Ok(result),
+			None => self.0.apply(val, gate, ty, config),
+		}
+	}
+
+	fn apply_tuple(&self, val: RunVal, ops: Vec<Op>, ty: Type, config: &Config) -> Ret<RunVal> {
+		match apply_stabilizer(&ops, &val, config) {
+			Some(result) => Ok(result),
+			None => self.0.apply_tuple(val, ops, ty, config),
+		}
+	}
+
+	fn apply_channel(&self, val: RunVal, kraus: &Channel, ty: Type) -> Ret<RunVal> {
+		self.0.apply_channel(val, kraus, ty)
+	}
+
+	fn sup(&self, vals: Vec<RunVal>) -> Ret<RunVal> {
+		self.0.sup(vals)
+	}
+
+	fn phase(&self, val: RunVal, phase: Phase) -> Ret<RunVal> {
+		match val {
+			// Stabilizer states do not track their global phase, but imaginary phases also scale the norm,
+			// so these fall back to the state vector
+			val @ RunVal::Stabilizer(_, _) if phase.im == 0.0 => Ok(val),
+			val => self.0.phase(val, phase),
+		}
+	}
+
+	fn measure(&self, val: RunVal, config: &Config) -> Ret<RunVal> {
+		match val {
+			RunVal::Stabilizer(mut tableau, t) if !config.density => {
+				let bits = tableau.measure(&mut *config.rng.get());
+				Ok(outcome_tuple(&t, &mut bits.into_iter().map(|b| b as usize)))
+			},
+			val => self.0.measure(val, config),
+		}
+	}
+
+	// Stabilizer states are measured on their state vector, so this is limited to `MAX_DENSE_QUBITS` qubits (unlike `measure`)
+	fn measure_part(&self, val: RunVal, k: usize, config: &Config) -> Ret<RunVal> {
+		if let RunVal::Stabilizer(ref tableau, _) = val {
+			if tableau.qubits > MAX_DENSE_QUBITS {
+				return err!("Cannot measure part of {} qubits without the stabilizer tableau (use `measure` instead)", tableau.qubits)
+			}
+		}
+		self.0.measure_part(val, k, config)
+	}
+
+	fn probs(&self, val: RunVal) -> Ret<(Vec<Real>, Type)> {
+		self.0.probs(val)
+	}
+}
+
+// Simulator running local gates on matrix product states (see `Config::set_bond_dim`), and other operations on the given backend
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct MpsBackend<B>(pub B);
+
+impl<B: Backend> Backend for MpsBackend<B> {
+	fn name(&self) -> &str {
+		"mps"
+	}
+
+	fn prepare(&self, val: RunVal) -> Ret<RunVal> {
+		self.0.prepare(val)
+	}
+
+	fn apply(&self, val: RunVal, gate: Op, ty: Type, config: &Config) -> Ret<RunVal> {
+		match apply_mps(::std::slice::from_ref(&gate), &val, config) {
+			Some(result) => Ok(result),
+			None => self.0.apply(val, gate, ty, config),
+		}
+	}
+
+	fn apply_tuple(&self, val: RunVal, ops: Vec<Op>, ty: Type, config: &Config) -> Ret<RunVal> {
+		match apply_mps(&ops, &val, config) {
+			Some(result) => Ok(result),
+			None => self.0.apply_tuple(val, ops, ty, config),
+		}
+	}
+
+	fn apply_channel(&self, val: RunVal, kraus: &Channel, ty: Type) -> Ret<RunVal> {
+		self.0.apply_channel(val, kraus, ty)
+	}
+
+	fn sup(&self, vals: Vec<RunVal>) -> Ret<RunVal> {
+		self.0.sup(vals)
+	}
+
+	fn phase(&self, val: RunVal, phase: Phase) -> Ret<RunVal> {
+		match val {
+			RunVal::Mps(mps, t) => Ok(RunVal::Mps(mps.phase(phase), t)),
+			val => self.0.phase(val, phase),
+		}
+	}
+
+	fn measure(&self, val: RunVal, config: &Config) -> Ret<RunVal> {
+		match val {
+			RunVal::Mps(mps, t) if !config.density => {
+				let outcomes = mps.measure(&mut *config.rng.get());
+				Ok(outcome_tuple(&t, &mut outcomes.into_iter()))
+			},
+			val => self.0.measure(val, config),
+		}
+	}
+
+	fn measure_part(&self, val: RunVal, k: usize, config: &Config) -> Ret<RunVal> {
+		self.0.measure_part(val, k, config)
+	}
+
+	fn probs(&self, val: RunVal) -> Ret<(Vec<Real>, Type)> {
+		self.0.probs(val)
+	}
+}
+
+// Run Clifford gates (one per group of qubits) on a stabilizer tableau, if enabled for the given input
+fn apply_stabilizer(ops: &[Op], val: &RunVal, config: &Config) -> Option<RunVal> {
+	let mode = config.stabilizer;
+	if mode == StabilizerMode::Never || config.density {
+		return None
+	}
+	let (mut tableau, t) = build_tableau(val)?;
+	let tracked = matches!(val, &RunVal::Stabilizer(_, _));
+	if !tracked && mode == StabilizerMode::Auto && tableau.qubits < STABILIZER_MIN_QUBITS {
+		return None
+	}
+	let mut gates = vec![];
+	let mut offset = 0;
+	for op in ops {
+		let qubits = qubit_count(op.len())?;
+		if !op.is_identity() {
+			gates.push((as_clifford(&op.to_gate())?, offset));
+		}
+		offset += qubits;
+	}
+	if offset != tableau.qubits {
+		return None
+	}
+	for (c, offset) in gates {
+		tableau.apply(&c, offset);
+	}
+	Some(RunVal::Stabilizer(tableau, t))
+}
+
+// Run gates on one or two neighboring components of a matrix product state, if enabled (stabilizer states are left as they are)
+fn apply_mps(ops: &[Op], val: &RunVal, config: &Config) -> Option<RunVal> {
+	let bond_cap = config.bond_dim?;
+	if config.density || matches!(val, &RunVal::Stabilizer(_, _)) {
+		return None
+	}
+	let (mut mps, t) = build_mps(val, bond_cap)?;
+	let dims = mps.dims();
+	let mut steps = vec![];
+	let mut k = 0;
+	for op in ops {
+		if op.width() != op.len() {
+			return None
+		}
+		let sites = if k < dims.len() && op.len() == dims[k] {1}
+			else if k + 1 < dims.len() && op.len() == dims[k] * dims[k + 1] {2}
+			else {return None};
+		steps.push((k, sites, op));
+		k += sites;
+	}
+	if k != dims.len() {
+		return None
+	}
+	for (k, sites, op) in steps {
+		if op.is_identity() {
+			continue
+		}
+		if sites == 1 {mps.apply_local(k, &op.to_gate())}
+		else {mps.apply_pair(k, &op.to_gate())}
+	}
+	Some(RunVal::Mps(mps, t))
+}
+
+// Apply each gate to the matching component of a pure state, one axis at a time
+fn apply_axes(ops: &[Op], val: &RunVal) -> Option<State> {
+	if is_mixed(val) || ops.iter().any(|op| op.width() != op.len()) {
+		return None
+	}
+	let dims = ops.iter().map(|op| op.len()).collect::<Vec<_>>();
+	let size = dims.iter().product();
+	let (s, _) = build_state_typed(val.clone()).ok()?;
+	if s.len() > size {
+		return None
+	}
+	let mut s = s.pad(size);
+	for (k, op) in ops.iter().enumerate() {
+		op.apply_axis(&mut s, &dims, k);
+	}
+	Some(s)
+}
+
+// Sparse form of a pure state which is already stored sparsely or is a large basis value
+fn sparse_input(val: &RunVal) -> Option<SparseState> {
+	let (s, _) = build_sparse(val)?;
+	match val {
+		&RunVal::Sparse(_, _) => Some(s),
+		_ if s.size >= SPARSE_MIN_SIZE => Some(s),
+		_ => None,
+	}
+}
+
+// Apply each gate to the matching component of a sparse state (see `apply_axes`)
+fn apply_axes_sparse(ops: &[Op], val: &RunVal) -> Option<SparseState> {
+	if ops.iter().any(|op| op.width() != op.len()) {
+		return None
+	}
+	let dims = ops.iter().map(|op| op.len()).collect::<Vec<_>>();
+	let size = dims.iter().product();
+	let s = sparse_input(val)?;
+	if s.size > size {
+		return None
+	}
+	let mut s = s.pad(size);
+	for (k, op) in ops.iter().enumerate() {
+		s = op.apply_axis_sparse(&s, &dims, k);
+	}
+	Some(s)
+}
+
+// Outcome value of the given type for a basis index
+pub fn outcome_val(t: &Type, i: usize) -> RunVal {
+	t.from_index(i).unwrap_or(RunVal::Index(i))
+}
+
+// Outcome values for each component of a tuple-typed register
+pub fn outcome_tuple(t: &Type, outcomes: &mut dyn Iterator<Item = usize>) -> RunVal {
+	match t {
+		&Type::Tuple(ref types) => RunVal::Tuple(types.iter().map(|t| outcome_tuple(t, outcomes)).collect()),
+		t => outcome_val(t, outcomes.next().unwrap_or(0)),
+	}
+}
+
+// Component dimensions, type of component `k`, and type of the remaining components
+pub fn split_tuple_type(t: &Type, k: usize, len: usize) -> Ret<(Vec<usize>, Type, Type)> {
+	let (mut types, dims) = tuple_dims(t, len)?;
+	if k >= types.len() {
+		return err!("Cannot measure component {} of type {}", k, t)
+	}
+	let part_type = types.remove(k);
+	Ok((dims, part_type, tuple_type(types)))
+}
+
+// Component types and dimensions of a tuple-typed state
+// (a single component of unknown type takes up the rest of a state with `len` entries)
+pub fn tuple_dims(t: &Type, len: usize) -> Ret<(Vec<Type>, Vec<usize>)> {
+	let types = match t {
+		&Type::Tuple(ref types) => types.clone(),
+		_ => return err!("Expected tuple-typed state: {}", t),
+	};
+	let known = types.iter().filter_map(|t| t.size()).product::<usize>();
+	if types.iter().filter(|t| t.size().is_none()).count() > 1 {
+		return err!("Cannot find components of type {}", t)
+	}
+	let dims = types.iter().map(|t| t.size().unwrap_or(len.div_ceil(known))).collect();
+	Ok((types, dims))
+}
+
+pub fn tuple_type(mut types: Vec<Type>) -> Type {
+	if types.len() == 1 {types.remove(0)} else {Type::Tuple(types)}
+}
+
+// Backend shared between a context and its children
+#[derive(Clone)]
+pub struct BackendRef(pub Rc<dyn Backend>);
+
+impl BackendRef {
+	pub fn new<B: Backend + 'static>(backend: B) -> BackendRef {
+		BackendRef(Rc::new(backend))
+	}
+}
+
+impl fmt::Debug for BackendRef {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, ":backend: {}", self.0.name())
+	}
+}
+
+impl PartialEq for BackendRef {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.0, &other.0)
+	}
+}
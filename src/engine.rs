@@ -1,21 +1,302 @@
-use rand::thread_rng;
-use rand::distributions::{Weighted, WeightedChoice, Sample};
-
-pub type Cf32 = num::complex::Complex32;
+use error::*;
+use rand::Rng;
+use num::{Zero, One};
+use num::complex::Complex;
+use linalg::{Matrix, eigh, hermitian_map, mul};
+use linalg;
+use sparse::*;
+use structured::*;
 
 #[macro_export]
 macro_rules! real {
-	($n:expr) => {::num::complex::Complex32::new(($n) as f32, 0_f32)}
+	($n:expr) => {::num::complex::Complex64::new(($n) as f64, 0_f64)}
 }
 
 #[macro_export]
 macro_rules! imag {
-	($n:expr) => {::num::complex::Complex32::new(0_f32, ($n) as f32)}
+	($n:expr) => {::num::complex::Complex64::new(0_f64, ($n) as f64)}
 }
 
-pub type State = Vec<Cf32>;
+pub type Real = f64;
+pub type Cf = Complex<Real>;
+pub type State = Vec<Cf>;
 pub type Gate = Vec<State>;
-pub type Phase = Cf32;
+pub type Phase = Cf;
+
+// Quantum channel given by its Kraus operators
+pub type Channel = Vec<Gate>;
+
+// Density matrix of a mixed state (`rho[i][j]` is the coefficient of |i⟩⟨j|)
+#[derive(Clone,Debug,PartialEq)]
+pub struct Density(pub Gate);
+
+// Gate representation chosen by the interpreter
+#[derive(Clone,Debug)]
+pub enum Op {
+	Dense(Gate),
+	Sparse(SparseGate),
+	Perm(Perm),
+	Diag(State),
+}
+
+// Gates with at least this many inputs are stored sparsely when mostly zero
+pub const SPARSE_MIN_SIZE: usize = 64;
+pub const SPARSE_MAX_DENSITY: f64 = 0.25;
+
+impl Op {
+	pub fn from_gate(g: Gate) -> Op {
+		if let Some(diag) = as_diagonal(&g) {
+			Op::Diag(diag)
+		}
+		else if let Some(perm) = as_permutation(&g) {
+			Op::Perm(perm)
+		}
+		else if g.len() >= SPARSE_MIN_SIZE && gate_density(&g) <= SPARSE_MAX_DENSITY {
+			Op::Sparse(SparseGate::from_dense(&g))
+		}
+		else {Op::Dense(g)}
+	}
+
+	pub fn to_gate(&self) -> Gate {
+		match self {
+			&Op::Dense(ref g) => g.clone(),
+			&Op::Sparse(ref g) => g.to_dense(),
+			&Op::Perm(ref p) => perm_to_gate(p),
+			&Op::Diag(ref d) => diag_to_gate(d),
+		}
+	}
+
+	pub fn to_sparse(&self) -> SparseGate {
+		match self {
+			&Op::Dense(ref g) => SparseGate::from_dense(g),
+			&Op::Sparse(ref g) => g.clone(),
+			&Op::Perm(ref p) => SparseGate {
+				width: p.len(),
+				cols: p.iter().map(|&j| {
+					let mut s = SparseState::new(p.len());
+					s.add(j, real!(1));
+					s
+				}).collect(),
+			},
+			&Op::Diag(ref d) => SparseGate {
+				width: d.len(),
+				cols: d.iter().enumerate().map(|(i, &n)| {
+					let mut s = SparseState::new(d.len());
+					s.add(i, n);
+					s
+				}).collect(),
+			},
+		}
+	}
+
+	// View as a permutation (diagonal gates count when they are the identity)
+	fn as_perm(&self) -> Option<Perm> {
+		match self {
+			&Op::Perm(ref p) => Some(p.clone()),
+			&Op::Diag(ref d) if d.iter().all(|n| n.is_one()) => Some((0..d.len()).collect()),
+			_ => None,
+		}
+	}
+
+	fn as_diag(&self) -> Option<State> {
+		match self {
+			&Op::Diag(ref d) => Some(d.clone()),
+			&Op::Perm(ref p) if is_identity(p) => Some(vec![real!(1); p.len()]),
+			_ => None,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		match self {
+			&Op::Dense(ref g) => g.len(),
+			&Op::Sparse(ref g) => g.len(),
+			&Op::Perm(ref p) => p.len(),
+			&Op::Diag(ref d) => d.len(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn is_identity(&self) -> bool {
+		match self {
+			&Op::Perm(ref p) => is_identity(p),
+			&Op::Diag(ref d) => d.iter().all(|n| n.is_one()),
+			_ => false,
+		}
+	}
+
+	pub fn apply_axis(&self, s: &mut State, dims: &[usize], k: usize) {
+		match self {
+			&Op::Dense(ref g) => apply_axis(s, dims, k, g),
+			&Op::Sparse(ref g) => apply_axis_sparse(s, dims, k, g),
+			&Op::Perm(ref p) => permute_axis(s, dims, k, p),
+			&Op::Diag(ref d) => scale_axis(s, dims, k, d),
+		}
+	}
+
+	// Nonzero outputs for input `i`
+	pub fn column(&self, i: usize) -> Vec<(usize, Cf)> {
+		match self {
+			&Op::Dense(ref g) => g.get(i).map(|s| {
+				s.iter().cloned().enumerate().filter(|&(_, n)| !n.is_zero()).collect()
+			}).unwrap_or_default(),
+			&Op::Sparse(ref g) => g.cols.get(i).map(|s| {
+				s.entries.iter().map(|(&j, &n)| (j, n)).collect()
+			}).unwrap_or_default(),
+			&Op::Perm(ref p) => p.get(i).map(|&j| vec![(j, real!(1))]).unwrap_or_default(),
+			&Op::Diag(ref d) => d.get(i).map(|&n| vec![(i, n)]).unwrap_or_default(),
+		}
+	}
+
+	pub fn apply_axis_sparse(&self, s: &SparseState, dims: &[usize], k: usize) -> SparseState {
+		sparse_apply_axis(s, dims, k, |i| self.column(i))
+	}
+
+	fn density(&self) -> f64 {
+		match self {
+			&Op::Dense(ref g) => gate_density(g),
+			&Op::Sparse(ref g) => g.nnz() as f64 / ::std::cmp::max(1, g.len() * g.width) as f64,
+			op => 1.0 / ::std::cmp::max(1, op.len()) as f64,
+		}
+	}
+}
+
+impl PartialEq for Op {
+	fn eq(&self, other: &Op) -> bool {
+		match (self, other) {
+			(&Op::Dense(ref a), &Op::Dense(ref b)) => a == b,
+			(&Op::Sparse(ref a), &Op::Sparse(ref b)) => a == b,
+			(&Op::Perm(ref a), &Op::Perm(ref b)) => a == b,
+			(&Op::Diag(ref a), &Op::Diag(ref b)) => a == b,
+			(a, b) => a.to_gate() == b.to_gate(),
+		}
+	}
+}
+
+impl Extract<Op> for State {
+	fn extract(self, op: Op) -> State {
+		match op {
+			Op::Dense(g) => self.extract(g),
+			Op::Sparse(g) => self.extract(g),
+			Op::Perm(p) => permute_state(self, &p),
+			Op::Diag(d) => scale_state(self, &d),
+		}
+	}
+}
+
+impl Extract<Op> for SparseState {
+	fn extract(self, op: Op) -> SparseState {
+		let mut state = SparseState::new(op.width());
+		for (i, x) in self.entries {
+			for (j, y) in op.column(i) {
+				state.add(j, x * y);
+			}
+		}
+		state
+	}
+}
+
+impl Extract<Op> for Density {
+	fn extract(self, op: Op) -> Density {
+		match op {
+			Op::Dense(g) => self.extract(g),
+			Op::Sparse(g) => self.extract(g),
+			Op::Perm(p) => permute_density(self, &p),
+			Op::Diag(d) => scale_density(self, &d),
+		}
+	}
+}
+
+impl Combine for Op {
+	// Kronecker products of large, mostly-zero gates stay sparse
+	fn combine(self, op: Op) -> Op {
+		if let (Some(a), Some(b)) = (self.as_diag(), op.as_diag()) {
+			return Op::Diag(a.combine(b))
+		}
+		if let (Some(a), Some(b)) = (self.as_perm(), op.as_perm()) {
+			return Op::Perm(combine_perms(&a, &b))
+		}
+		let sparse = match (&self, &op) {
+			(&Op::Sparse(_), _) | (_, &Op::Sparse(_)) => true,
+			_ => self.len() * op.len() >= SPARSE_MIN_SIZE && self.density() * op.density() <= SPARSE_MAX_DENSITY,
+		};
+		if sparse {Op::Sparse(self.to_sparse().combine(op.to_sparse()))}
+		else {Op::Dense(self.to_gate().combine(op.to_gate()))}
+	}
+}
+
+impl MatrixLike for Op {
+	fn width(&self) -> usize {
+		match self {
+			&Op::Dense(ref g) => g.width(),
+			&Op::Sparse(ref g) => g.width(),
+			op => op.len(),
+		}
+	}
+
+	fn unitarity(&self) -> Unitarity {
+		match self {
+			&Op::Dense(ref g) => g.unitarity(),
+			&Op::Sparse(ref g) => g.unitarity(),
+			&Op::Perm(_) => Unitarity::Unitary,
+			&Op::Diag(ref d) => {
+				if d.iter().all(|n| (n.norm() - 1.0).abs() <= EPSILON) {Unitarity::Unitary}
+				else {Unitarity::NonUnitary}
+			},
+		}
+	}
+
+	fn inverse(self) -> Op {
+		match self {
+			Op::Dense(g) => Op::Dense(g.inverse()),
+			Op::Sparse(g) => Op::Sparse(g.inverse()),
+			Op::Perm(p) => Op::Perm(invert_perm(&p)),
+			Op::Diag(d) => Op::Diag(d.into_iter().map(|n| n.conj()).collect()),
+		}
+	}
+
+	fn negate(self) -> Op {
+		match self {
+			Op::Dense(g) => Op::Dense(g.negate()),
+			Op::Sparse(g) => Op::Sparse(g.negate()),
+			Op::Diag(d) => Op::Diag(d.phase_flip()),
+			// Negated permutations keep a single entry per column
+			op => Op::Sparse(op.to_sparse().negate()),
+		}
+	}
+
+	fn power(self, p: Phase) -> Ret<Op> {
+		Ok(match self {
+			Op::Dense(g) => Op::Dense(g.power(p)?),
+			Op::Sparse(g) => Op::Sparse(g.power(p)?),
+			Op::Perm(ref perm) if p.im == 0.0 && p.re.fract() == 0.0 => Op::Perm(perm_power(perm, p.re)),
+			Op::Diag(d) => Op::Diag(d.into_iter().map(|n| principal_power(n, p)).collect::<Ret<_>>()?),
+			op => Op::from_gate(op.to_gate().power(p)?),
+		})
+	}
+
+	fn expm(self) -> Ret<Op> {
+		Ok(match self {
+			Op::Diag(d) => Op::Diag(d.into_iter().map(|n| n.exp()).collect()),
+			op => Op::from_gate(op.to_gate().expm()?),
+		})
+	}
+
+	fn logm(self) -> Ret<Op> {
+		Ok(match self {
+			Op::Diag(d) => {
+				let d = d.into_iter().map(|n| principal(n).ln()).collect::<State>();
+				if d.iter().any(|n| !n.re.is_finite()) {
+					return err!("Logarithm of a singular matrix")
+				}
+				Op::Diag(d)
+			},
+			op => Op::from_gate(op.to_gate().logm()?),
+		})
+	}
+}
 
 pub trait DebugPrint {
 	fn print(&self);
@@ -35,15 +316,22 @@ impl DebugPrint for Gate {
 	}
 }
 
+impl DebugPrint for Density {
+	fn print(&self) {
+		self.0.print();
+	}
+}
+
 pub trait Stateful
 where Self: ::std::marker::Sized {
 	fn pad(self, n: usize) -> Self;
 	fn sup(self, s: Self) -> Self;
 	fn normalized(self) -> Self;
-	fn phase(self, p: Phase) -> Self;
+	fn phase(self, p: Cf) -> Self;
 	fn phase_flip(self) -> Self;
-	fn prob_sum(&self) -> f32;
-	fn measure(self) -> usize;
+	fn prob_sum(&self) -> Real;
+	fn probs(&self) -> Vec<Real>;
+	fn measure<R: Rng>(self, rng: &mut R) -> usize;
 }
 
 impl Stateful for State {
@@ -63,42 +351,185 @@ impl Stateful for State {
 		self.into_iter().map(|s| s / div).collect()
 	}
 	
-	fn phase(self, p: Phase) -> State {
-		let n = p * ::std::f32::consts::PI;
-		let (cos, sin) = (n.cos(), n.sin());
-		self.into_iter().map(|x| Cf32::new(//TODO implement imaginary phases
-			cos.re * x.re + sin.re * x.im,
-			sin.re * x.re + cos.re * x.im,
-		)).collect()
+	// Multiply by (-1)^p = e^(iπp), matching the power of the gate `-I`.
+	// For p = x + yi, the real part rotates by πx radians and the imaginary part
+	// scales amplitudes by e^(-πy) (decay for y > 0, growth for y < 0).
+	fn phase(self, p: Cf) -> State {
+		let c = phase_factor(p);
+		self.into_iter().map(|x| x * c).collect()
 	}
 	
 	fn phase_flip(self) -> State {
 		self.into_iter().map(|x| -x).collect()
 	}
 	
-	fn prob_sum(&self) -> f32 {
-		self.iter().fold(0_f32, |a, &b| a + absq(b))
+	fn prob_sum(&self) -> Real {
+		self.iter().fold(0.0, |a, &b| a + b.norm_sqr())
+	}
+	
+	fn probs(&self) -> Vec<Real> {
+		self.iter().map(|&n| n.norm_sqr()).collect()
+	}
+	
+	fn measure<R: Rng>(self, rng: &mut R) -> usize {
+		sample_index(&self.probs(), rng)
+	}
+}
+
+impl Density {
+	// Pure state |s⟩⟨s|
+	pub fn from_state(s: &State) -> Density {
+		Density(s.iter().map(|x| s.iter().map(|y| x * y.conj()).collect()).collect())
+	}
+	
+	pub fn size(&self) -> usize {
+		self.0.len()
+	}
+	
+	// Probability of each basis outcome
+	pub fn diagonal(&self) -> Vec<Real> {
+		self.0.iter().enumerate().map(|(i, row)| row.get(i).map(|n| n.re).unwrap_or(0.0)).collect()
+	}
+	
+	pub fn trace(&self) -> Real {
+		self.diagonal().into_iter().fold(0.0, |a, b| a + b)
+	}
+	
+	// Non-selective measurement: discard all coherences, leaving a classical mixture of outcomes
+	pub fn decohere(self) -> Density {
+		Density(self.0.into_iter().enumerate().map(|(i, row)| {
+			row.into_iter().enumerate().map(|(j, n)| if i == j {n} else {real!(0)}).collect()
+		}).collect())
+	}
+	
+	// Ensemble of the given states with the given (unnormalized) probabilities
+	pub fn mix(parts: Vec<(Real, Density)>) -> Density {
+		let total = parts.iter().fold(0.0, |a, &(p, _)| a + p);
+		Density::sum(parts.into_iter().map(|(p, d)| (p / total, d)).collect())
+	}
+	
+	fn sum(parts: Vec<(Real, Density)>) -> Density {
+		let size = parts.iter().map(|&(_, ref d)| d.size()).max().unwrap_or(0);
+		let mut rho = vec![vec![real!(0); size]; size];
+		for (p, d) in parts {
+			for (i, row) in d.0.into_iter().enumerate() {
+				for (j, n) in row.into_iter().enumerate() {
+					rho[i][j] += n * p;
+				}
+			}
+		}
+		Density(rho)
+	}
+
+	// ρ -> Σ KρK†
+	pub fn apply_channel(self, kraus: &Channel) -> Ret<Density> {
+		let dim = kraus.first().map(|k| k.len()).unwrap_or(0);
+		if self.size() > dim {
+			return err!("Channel on {} dimensions applied to a {}-dimensional state", dim, self.size())
+		}
+		Ok(Density::sum(kraus.iter()
+			.map(|k| (1.0, self.clone().extract(k.clone())))
+			.collect()))
+	}
+
+	// Von Neumann entropy -Tr(ρ log2 ρ)
+	pub fn entropy(&self) -> Real {
+		let (vals, _) = eigh(&self.0);
+		vals.into_iter()
+			.filter(|&p| p > EPSILON * EPSILON)
+			.fold(0.0, |sum, p| sum - p * p.log2())
+	}
+
+	// Tr(ρ²), which is 1 for pure states
+	pub fn purity(&self) -> Real {
+		self.0.iter().fold(0.0, |sum, row| row.iter().fold(sum, |sum, n| sum + n.norm_sqr()))
+	}
+
+	// Wootters concurrence of a two-qubit state
+	pub fn concurrence(&self) -> Ret<Real> {
+		if self.size() != 4 {
+			return err!("Concurrence requires a two-qubit state, not one of size {}", self.size())
+		}
+		// ρ~ = (Y⊗Y) ρ* (Y⊗Y)
+		let sign = |i: usize| if i == 0 || i == 3 {-1.0} else {1.0};
+		let flipped = (0..4).map(|i| (0..4).map(|j| self.0[3 - i][3 - j].conj() * sign(i) * sign(j)).collect()).collect();
+		let root = hermitian_map(&self.0, |p| real!(p.max(0.0).sqrt()));
+		let (vals, _) = eigh(&mul(&mul(&root, &flipped), &root));
+		let vals = vals.into_iter().rev().map(|p| p.max(0.0).sqrt()).collect::<Vec<_>>();
+		Ok((vals[0] - vals[1] - vals[2] - vals[3]).max(0.0))
+	}
+
+	// Uhlmann fidelity (Tr √(√ρ σ √ρ))²
+	pub fn fidelity(&self, d: &Density) -> Real {
+		let size = ::std::cmp::max(self.size(), d.size());
+		let (a, b) = (self.clone().pad(size), d.clone().pad(size));
+		let root = hermitian_map(&a.0, |p| real!(p.max(0.0).sqrt()));
+		let (vals, _) = eigh(&mul(&mul(&root, &b.0), &root));
+		let sum = vals.into_iter().fold(0.0, |sum, p| sum + p.max(0.0).sqrt());
+		sum * sum
+	}
+
+	// Half the sum of the absolute eigenvalues of ρ - σ
+	pub fn trace_distance(&self, d: &Density) -> Real {
+		let size = ::std::cmp::max(self.size(), d.size());
+		let (a, b) = (self.clone().pad(size), d.clone().pad(size));
+		let diff = a.0.into_iter().zip(b.0).map(|(x, y)| x.into_iter().zip(y).map(|(x, y)| x - y).collect()).collect();
+		let (vals, _) = eigh(&diff);
+		vals.into_iter().fold(0.0, |sum, p| sum + p.abs()) / 2.0
 	}
 	
-	fn measure(self) -> usize {
-		let mut weights = vec![];
-		for (i, n) in self.into_iter().enumerate() {
-			weights.push(Weighted {
-				weight: (::std::u16::MAX as f32 / absq(n)) as u32,
-				item: i,
-			});
+	fn scale(self, n: Real) -> Density {
+		Density(self.0.into_iter().map(|row| row.into_iter().map(|x| x * n).collect()).collect())
+	}
+}
+
+impl Stateful for Density {
+	fn pad(self, n: usize) -> Density {
+		let size = ::std::cmp::max(self.size(), n);
+		let mut rho: Gate = self.0.into_iter().map(|row| row.pad(size)).collect();
+		while rho.len() < size {
+			rho.push(vec![real!(0); size]);
 		}
-		let mut wc = WeightedChoice::new(&mut weights);
-		let mut rng = thread_rng();
-		wc.sample(&mut rng)
+		Density(rho)
+	}
+	
+	// Mixed states have no coherent superposition, so this forms the equal-weight ensemble instead
+	fn sup(self, d: Density) -> Density {
+		Density::mix(vec![(1.0, self), (1.0, d)])
+	}
+	
+	fn normalized(self) -> Density {
+		let div = self.trace();
+		self.scale(1.0 / div)
+	}
+	
+	fn phase(self, p: Cf) -> Density {
+		// ρ -> cρc* only depends on the magnitude of the phase factor
+		self.scale(phase_factor(p).norm_sqr())
+	}
+	
+	fn phase_flip(self) -> Density {
+		self
+	}
+	
+	fn prob_sum(&self) -> Real {
+		self.trace()
+	}
+	
+	fn probs(&self) -> Vec<Real> {
+		self.diagonal().into_iter().map(|p| p.max(0.0)).collect()
+	}
+	
+	fn measure<R: Rng>(self, rng: &mut R) -> usize {
+		sample_index(&self.probs(), rng)
 	}
 }
 
-pub trait Extract {
-	fn extract(self, g: Gate) -> Self;
+pub trait Extract<G> {
+	fn extract(self, g: G) -> Self;
 }
 
-impl Extract for State {
+impl Extract<Gate> for State {
 	fn extract(self, g: Gate) -> State {
 		self.into_iter().zip(g).map(|(x, s)| {
 			s.into_iter().map(|y| x * y).collect()
@@ -106,7 +537,7 @@ impl Extract for State {
 	}
 }
 
-impl Extract for Gate {
+impl Extract<Gate> for Gate {
 	fn extract(self, g: Gate) -> Gate {
 		self.into_iter().map(|state| {
 			state.extract(g.clone())
@@ -114,6 +545,32 @@ impl Extract for Gate {
 	}
 }
 
+impl Extract<Gate> for Density {
+	// ρ -> UρU†
+	fn extract(self, g: Gate) -> Density {
+		let width = g.width();
+		let get = |i: usize, a: usize| g.get(i).and_then(|s| s.get(a)).cloned().unwrap_or(real!(0));
+		let mut rho = vec![vec![real!(0); width]; width];
+		for (i, row) in self.0.iter().enumerate() {
+			for (j, &n) in row.iter().enumerate() {
+				if n.is_zero() {
+					continue
+				}
+				for (a, out) in rho.iter_mut().enumerate() {
+					let u = get(i, a) * n;
+					if u.is_zero() {
+						continue
+					}
+					for (b, m) in out.iter_mut().enumerate() {
+						*m += u * get(j, b).conj();
+					}
+				}
+			}
+		}
+		Density(rho)
+	}
+}
+
 pub trait Combine {
 	fn combine(self, s: Self) -> Self;
 }
@@ -142,28 +599,54 @@ impl Combine for Gate {
 	}
 }
 
+impl Combine for Density {
+	fn combine(self, d: Density) -> Density {
+		Density(self.0.combine(d.0))
+	}
+}
+
+// Tolerance used when comparing matrix entries
+pub const EPSILON: f64 = 0.0001;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Unitarity {
+	Unitary,
+	// Columns are orthonormal but the output space is larger than the input space
+	Isometry,
+	NonUnitary,
+}
+
+impl fmt::Display for Unitarity {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", match self {
+			&Unitarity::Unitary => "unitary",
+			&Unitarity::Isometry => "isometric",
+			&Unitarity::NonUnitary => "non-unitary",
+		})
+	}
+}
+
 pub trait MatrixLike {
 	fn width(&self) -> usize;
 	
-	fn is_unitary(&self) -> bool;
+	fn unitarity(&self) -> Unitarity;
+	fn is_unitary(&self) -> bool {self.unitarity() == Unitarity::Unitary}
 	
 	fn inverse(self) -> Self;
 	fn negate(self) -> Self;
-	fn power(self, p: Phase) -> Self;
+	fn power(self, p: Cf) -> Ret<Self> where Self: Sized;
+	// Matrix exponential and principal logarithm
+	fn expm(self) -> Ret<Self> where Self: Sized;
+	fn logm(self) -> Ret<Self> where Self: Sized;
 }
 
 impl MatrixLike for Gate {
 	fn width(&self) -> usize {self.iter().map(|s| s.len()).max().unwrap_or_else(|| 0)}
 	
-	fn is_unitary(&self) -> bool {
-		unimplemented!()
-		// // self * self.inv() == identity
-		// for (i, s) in self.iter().enumerate() {
-		// 	for (j, n) in s[(i + 1)..].iter().enumerate() {
-		// 		...
-		// 	}
-		// }
-		// true
+	fn unitarity(&self) -> Unitarity {
+		if !non_orthonormal_inputs(self).is_empty() {Unitarity::NonUnitary}
+		else if self.width() > self.len() {Unitarity::Isometry}
+		else {Unitarity::Unitary}
 	}
 	
 	fn inverse(self) -> Gate {
@@ -186,66 +669,295 @@ impl MatrixLike for Gate {
 		self.into_iter().map(|dim| dim.into_iter().map(|s| -s).collect()).collect()
 	}
 	
-	fn power(self, p: Phase) -> Self {
-		use lapacke::*;
-		use num::Zero;
-		use num::One;
+	fn power(self, p: Cf) -> Ret<Self> {
 		if p.is_one() {
-			return self
+			return Ok(self)
+		}
+		// Only exactly diagonal gates skip the Schur decomposition
+		if let Some(d) = as_diagonal(&self) {
+			return Ok(diag_to_gate(&d.into_iter().map(|n| principal_power(n, p)).collect::<Ret<_>>()?))
 		}
+		linalg::powm(&square_matrix(self), p)
+	}
+
+	fn expm(self) -> Ret<Self> {
+		if let Some(d) = as_diagonal(&self) {
+			return Ok(diag_to_gate(&d.into_iter().map(|n| n.exp()).collect()))
+		}
+		linalg::expm(&square_matrix(self))
+	}
+
+	fn logm(self) -> Ret<Self> {
+		let g = match as_diagonal(&self) {
+			Some(d) => diag_to_gate(&d.into_iter().map(|n| principal(n).ln()).collect()),
+			None => linalg::logm(&square_matrix(self))?,
+		};
+		if g.iter().any(|s| s.iter().any(|n| !n.re.is_finite() || !n.im.is_finite())) {
+			return err!("Logarithm of a singular matrix")
+		}
+		Ok(g)
+	}
+}
 
-		let size = ::std::cmp::max(self.len(), self.width());
-		let mut mat = vec![c64::zero(); size * size];
-		for (i, s) in self.into_iter().enumerate() {
-			for (j, n) in s.into_iter().enumerate() {
-				mat[i * size + j] = c64::new(n.re as f64, n.im as f64);
+// Discard rounding error in the imaginary part so that negative eigenvalues stay on the principal branch (e.g. (-1)^p = e^(iπp))
+pub fn principal(n: Cf) -> Cf {
+	if n.im.abs() <= n.norm() * Real::EPSILON.sqrt() {Complex::new(n.re, 0.0)} else {n}
+}
+
+// Principal power of a number, where zero stays zero for powers with a positive real part
+pub fn principal_power(n: Cf, p: Cf) -> Ret<Cf> {
+	if !n.is_zero() {Ok(principal(n).powc(p))}
+	else if p.is_zero() {Ok(Complex::one())}
+	else if p.re > 0.0 {Ok(Complex::zero())}
+	else {err!("Cannot take a non-positive power of a singular gate")}
+}
+
+// Gate as a square matrix, padding isometries with zeros
+// The matrix is stored transposed, which commutes with analytic functions
+fn square_matrix(g: Gate) -> Matrix {
+	let size = ::std::cmp::max(g.len(), g.width());
+	let mut mat = vec![vec![Complex::zero(); size]; size];
+	for (i, s) in g.into_iter().enumerate() {
+		for (j, n) in s.into_iter().enumerate() {
+			mat[i][j] = n;
+		}
+	}
+	mat
+}
+
+// Inner product ⟨a|b⟩
+pub fn overlap(a: &State, b: &State) -> Cf {
+	a.iter().zip(b).fold(Complex::zero(), |sum, (x, y)| sum + x.conj() * y)
+}
+
+// e^(iπp)
+pub fn phase_factor(p: Cf) -> Cf {
+	(Cf::i() * p * ::std::f64::consts::PI).exp()
+}
+
+pub fn is_hermitian(g: &Gate) -> bool {
+	let get = |i: usize, j: usize| g[i].get(j).cloned().unwrap_or(Complex::zero());
+	g.width() <= g.len() && (0..g.len()).all(|i| (0..g.len()).all(|j| (get(i, j) - get(j, i).conj()).norm() < EPSILON))
+}
+
+// Choose an index with probability proportional to its weight (Born rule)
+pub fn sample_index<R: Rng>(probs: &[Real], rng: &mut R) -> usize {
+	let total = probs.iter().fold(0.0, |a, &b| a + b);
+	let mut r = rng.gen::<f64>() * total;
+	for (i, &p) in probs.iter().enumerate() {
+		if r < p {
+			return i
+		}
+		r -= p;
+	}
+	// Rounding error; fall back to the last possible outcome
+	probs.iter().rposition(|&p| p > 0.0).unwrap_or(0)
+}
+
+// Split a basis index of a system with the given subsystem dimensions (most significant first)
+// into the index of subsystem `k` and the index of the remaining subsystems
+pub fn split_index(dims: &[usize], k: usize, i: usize) -> (usize, usize) {
+	let inner: usize = dims[k + 1..].iter().product();
+	((i / inner) % dims[k], (i / (inner * dims[k])) * inner + i % inner)
+}
+
+// Outcome probabilities when measuring subsystem `k`
+pub fn part_probs(probs: &[Real], dims: &[usize], k: usize) -> Vec<Real> {
+	let mut part = vec![0.0; dims[k]];
+	for (i, &p) in probs.iter().enumerate() {
+		part[split_index(dims, k, i).0] += p;
+	}
+	part
+}
+
+// Normalized state of the remaining subsystems after subsystem `k` is found in basis state `m`
+pub fn collapse_state_part(s: &State, dims: &[usize], k: usize, m: usize) -> State {
+	let mut rest = vec![real!(0); dims.iter().product::<usize>() / dims[k]];
+	for (i, &n) in s.iter().enumerate() {
+		let (j, r) = split_index(dims, k, i);
+		if j == m {
+			rest[r] = n;
+		}
+	}
+	rest.normalized()
+}
+
+// Normalized density matrix of the remaining subsystems after subsystem `k` is found in basis state `m`
+pub fn collapse_density_part(rho: &Density, dims: &[usize], k: usize, m: usize) -> Density {
+	let size = dims.iter().product::<usize>() / dims[k];
+	let mut rest = vec![vec![real!(0); size]; size];
+	for (i, row) in rho.0.iter().enumerate() {
+		let (a, r) = split_index(dims, k, i);
+		for (j, &n) in row.iter().enumerate() {
+			let (b, c) = split_index(dims, k, j);
+			if a == m && b == m {
+				rest[r][c] = n;
 			}
 		}
+	}
+	Density(rest).normalized()
+}
 
-		let mut vals = vec![c64::zero(); size];
-		let mut vecs = vec![c64::zero(); size * size];
-		// let mut ivecs: Vec<c64>;
-		unsafe {
-			fn wrap_status(status: i32) {
-				if status != 0 {
-					panic!(status)
-				}
+// Ensemble of (outcome of subsystem `k`, remaining state) pairs, with the outcome as the most significant subsystem
+pub fn decohere_part(rho: &Density, dims: &[usize], k: usize) -> Density {
+	let size: usize = dims.iter().product();
+	let rest = size / dims[k];
+	let mut mixed = vec![vec![real!(0); size]; size];
+	for (i, row) in rho.0.iter().enumerate() {
+		let (a, r) = split_index(dims, k, i);
+		for (j, &n) in row.iter().enumerate() {
+			let (b, c) = split_index(dims, k, j);
+			if a == b {
+				mixed[a * rest + r][b * rest + c] = n;
 			}
-			// let mut pivots = vec![0; size];
-			let size = size as i32;
-			wrap_status(zgeev(Layout::RowMajor, b'N', b'V', size, &mut mat[..], size, &mut vals[..], &mut [], size, &mut vecs[..], size));
-			// ivecs = vecs.clone();
-			// wrap_status(zgetrf(Layout::RowMajor, size, size, &mut ivecs, size, &mut pivots));
-			// wrap_status(zgetri(Layout::RowMajor, size, &mut ivecs, size, &pivots[..]));
 		}
+	}
+	Density(mixed)
+}
 
-		fn to_state(v: Vec<c64>) -> State {
-			v.into_iter().map(|c| Cf32::new(c.re as f32, c.im as f32)).collect()
+// Split a basis index into the indices of the kept subsystems and of the traced subsystems
+fn split_subsystems(dims: &[usize], traced: &[usize], mut i: usize) -> (usize, usize) {
+	let (mut kept, mut kept_scale, mut other, mut other_scale) = (0, 1, 0, 1);
+	for (k, &d) in dims.iter().enumerate().rev() {
+		if traced.contains(&k) {
+			other += (i % d) * other_scale;
+			other_scale *= d;
 		}
-		fn to_gate(v: Vec<c64>, n: usize) -> Gate {
-			(0..n).map(|i| to_state(v[n * i .. n * (i + 1)].to_vec())).collect()
+		else {
+			kept += (i % d) * kept_scale;
+			kept_scale *= d;
 		}
-		let vals = to_state(vals);
-		let vecs = to_gate(vecs, size);
-		// let ivecs = to_gate(ivecs, size);
-		let ivecs = vecs.clone().inverse();
+		i /= d;
+	}
+	(kept, other)
+}
 
-		let diag = (0..size).map(|i| {
-			let mut vec = vec![real!(0); size];
-			vec[i] = vals[i].powc(p);
-			vec
-		}).collect();
-		ivecs.extract(diag).extract(vecs)
+// Reduced density matrix after tracing out the given subsystems
+pub fn partial_trace(rho: &Density, dims: &[usize], traced: &[usize]) -> Density {
+	let size = dims.iter().enumerate().filter(|&(k, _)| !traced.contains(&k)).map(|(_, &d)| d).product();
+	let mut reduced = vec![vec![real!(0); size]; size];
+	for (i, row) in rho.0.iter().enumerate() {
+		let (a, r) = split_subsystems(dims, traced, i);
+		for (j, &n) in row.iter().enumerate() {
+			let (b, c) = split_subsystems(dims, traced, j);
+			if r == c {
+				reduced[a][b] += n;
+			}
+		}
 	}
+	Density(reduced)
+}
+
+// Input indices whose output states are not normalized or not orthogonal to another output
+pub fn non_orthonormal_inputs(g: &Gate) -> Vec<usize> {
+	(0..g.len()).filter(|&i| {
+		(overlap(&g[i], &g[i]) - Complex::one()).norm() > EPSILON
+			|| (0..g.len()).any(|j| i != j && overlap(&g[i], &g[j]).norm() > EPSILON)
+	}).collect()
+}
+
+// Independent channels acting on each component of a tuple
+pub fn combine_channels(a: &Channel, b: &Channel) -> Channel {
+	a.iter().flat_map(|ka| b.iter().map(move |kb| ka.clone().combine(kb.clone()))).collect()
+}
+
+// Apply a gate to axis `k` of a state with the given subsystem dimensions (most significant first),
+// leaving the other axes untouched. The gate must map the axis onto itself.
+pub fn apply_axis(s: &mut State, dims: &[usize], k: usize, g: &Gate) {
+	let d = dims[k];
+	let inner: usize = dims[k + 1..].iter().product();
+	let outer = s.len() / (d * inner);
+	let mut fiber = vec![Complex::zero(); d];
+	for o in 0..outer {
+		for r in 0..inner {
+			let base = o * d * inner + r;
+			for (m, x) in fiber.iter_mut().enumerate() {
+				*x = s[base + m * inner];
+			}
+			if fiber.iter().all(|x| x.is_zero()) {
+				continue
+			}
+			for j in 0..d {
+				s[base + j * inner] = fiber.iter().zip(g).fold(Complex::zero(), |sum, (x, col)| {
+					sum + x * col.get(j).cloned().unwrap_or(Complex::zero())
+				});
+			}
+		}
+	}
+}
+
+// Apply one gate per axis without forming their Kronecker product
+pub fn apply_axes(s: State, dims: &[usize], gates: &[Gate]) -> State {
+	let mut s = s.pad(dims.iter().product());
+	for (k, g) in gates.iter().enumerate() {
+		apply_axis(&mut s, dims, k, g);
+	}
+	s
+}
+
+// Check that Σ K†K = I (the channel preserves the trace of every state)
+pub fn is_trace_preserving(kraus: &Channel) -> bool {
+	let width = kraus.iter().map(|k| ::std::cmp::max(k.len(), k.width())).max().unwrap_or(0);
+	let get = |k: &Gate, i: usize, o: usize| k.get(i).and_then(|s| s.get(o)).cloned().unwrap_or(Complex::zero());
+	(0..width).all(|i| (0..width).all(|j| {
+		let sum: Cf = kraus.iter().fold(Complex::zero(), |a, k| a + (0..width).fold(Complex::zero(), |b, o| b + get(k, i, o).conj() * get(k, j, o)));
+		let expected = if i == j {Complex::one()} else {Complex::zero()};
+		(sum - expected).norm() < EPSILON
+	}))
+}
+
+// Flip between the first two basis states with probability `p`
+pub fn bit_flip_channel(p: Real) -> Channel {
+	let q = (1.0 - p).sqrt();
+	vec![
+		vec![vec![real!(q), real!(0)], vec![real!(0), real!(q)]],
+		vec![vec![real!(0), real!(p.sqrt())], vec![real!(p.sqrt()), real!(0)]],
+	]
+}
+
+// Replace a `dim`-dimensional state with the maximally mixed state with probability `p`
+pub fn depolarizing_channel(p: Real, dim: usize) -> Channel {
+	let q = (1.0 - p).sqrt();
+	let mut kraus: Channel = vec![(0..dim).map(|i| get_state(i).pad(dim).into_iter().map(|n| n * q).collect()).collect()];
+	let w = (p / dim as f64).sqrt();
+	for i in 0..dim {
+		for j in 0..dim {
+			let mut k = vec![vec![real!(0); dim]; dim];
+			k[i][j] = real!(w);
+			kraus.push(k);
+		}
+	}
+	kraus
+}
+
+// Decay from |1⟩ to |0⟩ with probability `g`
+pub fn amplitude_damping_channel(g: Real) -> Channel {
+	vec![
+		vec![vec![real!(1), real!(0)], vec![real!(0), real!((1.0 - g).sqrt())]],
+		vec![vec![real!(0), real!(0)], vec![real!(g.sqrt()), real!(0)]],
+	]
+}
+
+// Lose the relative phase between |0⟩ and |1⟩ with probability `l`
+pub fn phase_damping_channel(l: Real) -> Channel {
+	vec![
+		vec![vec![real!(1), real!(0)], vec![real!(0), real!((1.0 - l).sqrt())]],
+		vec![vec![real!(0), real!(0)], vec![real!(0), real!(l.sqrt())]],
+	]
 }
 
 // Create a superposition of the given states
 pub fn create_sup(states: Vec<State>) -> State {
-	let div = (states.len() as f32).sqrt();
+	let div = (states.len() as f64).sqrt();
 	states.into_iter().fold(vec![], |a, b| pad_zip(a, b, |x, y| x + y))
 		.into_iter().map(|x| x / div).collect()
 }
 
+// Create a linear combination of the given states (without normalizing)
+pub fn create_amp(terms: Vec<(Cf, State)>) -> State {
+	terms.into_iter().fold(vec![], |a, (w, b)| pad_zip(a, b, |x, y| x + y * w))
+}
+
 // Create a unit vector state in the given Hilbert dimension
 pub fn get_state(n: usize) -> State {
 	let mut state = vec![];
@@ -256,8 +968,8 @@ pub fn get_state(n: usize) -> State {
 	state
 }
 
-fn pad_zip<T>(a: State, b: State, f: T) -> State
-where T: Fn(Cf32, Cf32) -> Cf32 {
+fn pad_zip<F>(a: State, b: State, f: F) -> State
+where F: Fn(Cf, Cf) -> Cf {
 	let zero = real!(0);
 	// let (a, b) = if a.len() > b.len() {(a, b)} else {(b, a)};
 	let max_len = ::std::cmp::max(a.len(), b.len());
@@ -277,10 +989,6 @@ where T: Fn(Cf32, Cf32) -> Cf32 {
 	state
 }
 
-fn absq(n: Cf32) -> f32 {
-	n.re * n.re + n.im * n.im
-}
-
 use std::fmt;
 pub struct StateView<'a>(pub &'a State);
 impl<'a> fmt::Display for StateView<'a> {
@@ -289,8 +997,23 @@ impl<'a> fmt::Display for StateView<'a> {
 	}
 }
 
-fn round(f: Cf32, d: i32) -> Cf32 {
-	let m = Cf32::new(10_f32.powi(d), 0_f32);
+// Nonzero amplitudes of a sparse state, by index
+pub struct SparseView<'a>(pub &'a SparseState);
+impl<'a> fmt::Display for SparseView<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "sparse{{{}}}", self.0.entries.iter().map(|(i, &n)| format!("{}: {}", i, round(n, 4))).collect::<Vec<String>>().join(", "))
+	}
+}
+
+pub struct DensityView<'a>(pub &'a Density);
+impl<'a> fmt::Display for DensityView<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "[{}]", (self.0).0.iter().map(|s| format!("{}", StateView(s))).collect::<Vec<String>>().join(", "))
+	}
+}
+
+fn round(f: Cf, d: i32) -> Cf {
+	let m = 10_f64.powi(d);
 	let f = f * m;
-	Cf32::new(f.re.round(), f.im.round()) / m
-}
\ No newline at end of file
+	Complex::new(f.re.round(), f.im.round()) / m
+}
@@ -1,12 +1,19 @@
 use error::*;
 use ast::*;
 use engine::*;
+use stabilizer::*;
+use mps::Mps;
+use sparse::*;
+use observable::Observable;
 use types::*;
 use eval_static::*;
+use backend::*;
 
 use std::fmt;
 use std::rc::Rc;
+use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
+use rand::{thread_rng, Rng, SeedableRng, StdRng};
 
 #[derive(Clone)]
 pub struct Macro(pub Ident, pub Rc<dyn Fn(&Exp, &Context) -> Ret<RunVal>>);
@@ -29,19 +36,31 @@ impl PartialEq for Macro {
 #[derive(Clone,Debug,PartialEq)]
 pub enum RunVal {
 	Index(usize),
+	Real(Real),
+	Complex(Cf),
 	String(String),
 	Data(Rc<DataType>, usize),
 	Tuple(Vec<RunVal>),
 	Func(Rc<Context>, Pat, Exp, Type),
 	Macro(Macro),
 	State(State, Type),
-	Gate(Gate),
+	// Large, mostly-zero pure state storing only its nonzero amplitudes
+	Sparse(SparseState, Type),
+	Density(Density, Type),
+	Stabilizer(Tableau, Type),
+	// Tuple-typed state stored as a matrix product state (kept apart from `State`, like `Stabilizer`)
+	Mps(Mps, Type),
+	Gate(Op),
+	Channel(Channel),
+	Observable(Observable),
 }
 
 impl fmt::Display for RunVal {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self {
 			&RunVal::Index(ref n) => write!(f, "{}", n),
+			&RunVal::Real(ref n) => write!(f, "{}", n),
+			&RunVal::Complex(ref n) => write!(f, "{}", n),
 			&RunVal::String(ref s) => write!(f, "{:?}", s),
 			&RunVal::Data(ref dt, ref index) => write!(f, "{}", dt.variants[*index]),
 			&RunVal::Tuple(ref vals) => write!(f, "({})", vals.iter().map(|val| format!("{}", val)).collect::<Vec<_>>().join(", ")),
@@ -52,9 +71,125 @@ impl fmt::Display for RunVal {
 			} else {
 				write!(f, "{}", StateView(state))
 			},
-			&RunVal::Gate(ref gate) => write!(f, "[{}]", gate.iter().map(|state| format!("{}", StateView(state))).collect::<Vec<_>>().join(", ")),
+			&RunVal::Sparse(ref state, ref ty) => if ty != &Type::Any {
+				write!(f, "{}: {}", SparseView(state), ty)
+			} else {
+				write!(f, "{}", SparseView(state))
+			},
+			&RunVal::Density(ref rho, ref ty) => if ty != &Type::Any {
+				write!(f, "{}: {}", DensityView(rho), ty)
+			} else {
+				write!(f, "{}", DensityView(rho))
+			},
+			&RunVal::Stabilizer(ref tableau, ref ty) => if ty != &Type::Any {
+				write!(f, "{}: {}", tableau, ty)
+			} else {
+				write!(f, "{}", tableau)
+			},
+			&RunVal::Mps(ref mps, ref ty) => if ty != &Type::Any {
+				write!(f, "{}: {}", mps, ty)
+			} else {
+				write!(f, "{}", mps)
+			},
+			&RunVal::Gate(Op::Dense(ref gate)) => write!(f, "[{}]", gate.iter().map(|state| format!("{}", StateView(state))).collect::<Vec<_>>().join(", ")),
+			&RunVal::Gate(Op::Sparse(ref gate)) => write!(f, "sparse[{}]", gate.cols.iter().map(|state| format!("{{{}}}", state.entries.iter()
+				.map(|(i, n)| format!("{}: {}", i, n))
+				.collect::<Vec<_>>().join(", "))).collect::<Vec<_>>().join(", ")),
+			&RunVal::Gate(Op::Perm(ref perm)) => write!(f, "perm[{}]", perm.iter().map(|j| format!("{}", j)).collect::<Vec<_>>().join(", ")),
+			&RunVal::Gate(Op::Diag(ref diag)) => write!(f, "diag[{}]", diag.iter().map(|n| format!("{}", n)).collect::<Vec<_>>().join(", ")),
+			&RunVal::Channel(ref kraus) => write!(f, "channel{{{}}}", kraus.iter().map(|gate| format!("[{}]", gate.iter().map(|state| format!("{}", StateView(state))).collect::<Vec<_>>().join(", "))).collect::<Vec<_>>().join(", ")),
+			&RunVal::Observable(ref obs) => write!(f, "{}", obs),
+		}
+	}
+}
+
+// Random number generator shared between a context and its children (imported files get a fork, see `Context::import`)
+#[derive(Clone)]
+pub struct Random(Rc<RefCell<StdRng>>);
+
+impl Random {
+	pub fn new() -> Random {
+		Random::seeded(thread_rng().gen())
+	}
+	
+	pub fn seeded(seed: usize) -> Random {
+		Random(Rc::new(RefCell::new(StdRng::from_seed(&[seed][..]))))
+	}
+	
+	pub fn reseed(&self, seed: usize) {
+		self.0.borrow_mut().reseed(&[seed][..]);
+	}
+	
+	// Separate generator seeded from this one, so that reseeding either leaves the other alone
+	pub fn fork(&self) -> Random {
+		Random::seeded(self.get().gen())
+	}
+	
+	pub fn get(&self) -> RefMut<'_, StdRng> {
+		self.0.borrow_mut()
+	}
+}
+
+impl Default for Random {
+	fn default() -> Random {
+		Random::new()
+	}
+}
+
+impl fmt::Debug for Random {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, ":random:")
+	}
+}
+
+impl PartialEq for Random {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.0, &other.0)
+	}
+}
+
+// Evaluation settings (shared with imported modules)
+#[derive(Clone,Debug,PartialEq)]
+pub struct Config {
+	// Represent measurements as mixed states rather than sampling an outcome
+	pub density: bool,
+	// Reject extract gates which are not unitary or isometric
+	pub strict: bool,
+	// Use of the stabilizer simulator for Clifford gates
+	pub stabilizer: StabilizerMode,
+	// Simulate tuple-typed states as matrix product states with at most this bond dimension (see `set_bond_dim`)
+	pub bond_dim: Option<usize>,
+	// Source of measurement outcomes
+	pub rng: Random,
+	// Simulator for state preparation, gates, superposition, phase and measurement
+	pub backend: BackendRef,
+}
+
+impl Config {
+	pub fn new() -> Config {
+		Config {
+			density: false,
+			strict: false,
+			stabilizer: StabilizerMode::Auto,
+			bond_dim: None,
+			rng: Random::new(),
+			backend: default_backend(),
 		}
 	}
+	
+	pub fn set_bond_dim(&mut self, bond: usize) -> Ret {
+		if bond == 0 {
+			return err!("Bond dimension must be at least 1")
+		}
+		self.bond_dim = Some(bond);
+		Ok(())
+	}
+}
+
+impl Default for Config {
+	fn default() -> Config {
+		Config::new()
+	}
 }
 
 #[derive(Clone,Debug,PartialEq)]
@@ -62,6 +197,7 @@ pub struct Context {
 	path: String,
 	vars: HashMap<Ident, RunVal>,
 	types: TypeContext,
+	config: Config,
 }
 
 impl Context {
@@ -70,6 +206,7 @@ impl Context {
 			path,
 			vars: HashMap::new(),
 			types: TypeContext::new(),
+			config: Config::new(),
 		}
 	}
 	
@@ -77,6 +214,27 @@ impl Context {
 		&self.path
 	}
 	
+	pub fn config(&self) -> &Config {
+		&self.config
+	}
+	
+	pub fn config_mut(&mut self) -> &mut Config {
+		&mut self.config
+	}
+	
+	pub fn backend(&self) -> &dyn Backend {
+		&*self.config.backend.0
+	}
+	
+	pub fn set_backend<B: Backend + 'static>(&mut self, backend: B) {
+		self.config.backend = BackendRef::new(backend)
+	}
+	
+	// Make all subsequent measurements reproducible
+	pub fn set_seed(&self, seed: usize) {
+		self.config.rng.reseed(seed)
+	}
+	
 	pub fn types(&self) -> &TypeContext {
 		&self.types
 	}
@@ -127,7 +285,10 @@ impl Context {
 			let mut import_dir = import_path.clone();
 			import_dir.pop();
 			let file = import_path.to_string_lossy().to_string();
-			let ctx = stdlib::create_ctx(&import_dir.to_string_lossy())?;
+			let mut ctx = stdlib::create_ctx(&import_dir.to_string_lossy())?;
+			ctx.config = self.config.clone();
+			// Imported files draw from their own generator, so that `seed` within them does not affect the importer
+			ctx.config.rng = self.config.rng.fork();
 			(ctx, file)
 		};
 		let exp = parser::parse_resource(&file)?;
@@ -150,6 +311,8 @@ pub struct Module {
 pub fn eval_exp(exp: &Exp, ctx: &Context) -> RunVal {
 	match exp {
 		&Exp::Index(n) => RunVal::Index(n),
+		&Exp::Real(n) => RunVal::Real(n),
+		&Exp::Complex(n) => RunVal::Complex(n),
 		&Exp::String(ref s) => RunVal::String(s.to_string()),
 		&Exp::Var(ref id) => ctx.find_var(id).unwrap(),
 		&Exp::Scope(ref decls, ref ret) => {
@@ -164,11 +327,11 @@ pub fn eval_exp(exp: &Exp, ctx: &Context) -> RunVal {
 		&Exp::Concat(ref args) => {
 			//TODO adjacent gates
 			if args.len() == 1 {
-				if let Some(gate) = build_gate(&eval_exp(&args[0], ctx), ctx) {
+				if let Some(gate) = build_gate(&eval_exp(&args[0], ctx)) {
 					return RunVal::Gate(gate)
 				}
 			}
-			let div = (args.len() as f32).sqrt();
+			let div = (args.len() as Real).sqrt();
 			let states = args.iter()
 				.map(|e| build_state_typed(eval_exp(e, ctx)))
 				.collect::<Ret<Vec<(State, Type)>>>().unwrap();
@@ -185,6 +348,9 @@ pub fn eval_exp(exp: &Exp, ctx: &Context) -> RunVal {
 			if let Some(b) = build_bool(&val) {
 				eval_exp(if b {then_exp} else {else_exp}, ctx)
 			}
+			else if ctx.config().density || is_mixed(&val) {
+				eval_mixed_cond(val, then_exp, else_exp, ctx).unwrap()
+			}
 			else {
 				// TODO: consider removing in favor of using extract gates for explicitness
 //				let state = build_state(val);
@@ -206,20 +372,51 @@ pub fn eval_exp(exp: &Exp, ctx: &Context) -> RunVal {
 			match eval_exp(target, ctx) {
 				// TODO proper tuple function evaluation
 				RunVal::Func(fn_ctx_rc, pat, body, _ty) => {
+					let val = eval_exp(arg, ctx);
 					let mut fn_ctx = (*fn_ctx_rc).clone();
-					assign_pat(&pat, &eval_exp(arg, ctx), &mut fn_ctx).unwrap();
-					eval_exp(&body, &fn_ctx)
+					// Functions follow the settings of the caller rather than those of their definition
+					fn_ctx.config = ctx.config.clone();
+					assign_pat(&pat, &val, &mut fn_ctx).unwrap();
+					match body {
+						Exp::Extract(ref input, ref cases) => match eval_exp(input, &fn_ctx) {
+							// Gate functions on stabilizer, matrix product and sparse states only cover their cases,
+							// rather than being padded to the (possibly huge) size of the state
+							input @ RunVal::Stabilizer(_, _) | input @ RunVal::Mps(_, _) | input @ RunVal::Sparse(_, _) => {
+								let (gate, gt) = create_extract_gate_typed(cases, 0, &fn_ctx).unwrap();
+								fn_ctx.backend().apply(input, Op::from_gate(gate), gt, fn_ctx.config()).unwrap()
+							},
+							input => eval_extract(input, cases, &fn_ctx).unwrap(),
+						},
+						body => eval_exp(&body, &fn_ctx),
+					}
 				},
 				RunVal::Macro(Macro(_, handle)) => handle(arg, ctx).unwrap(),
 				RunVal::Gate(gate) => {
-					let (s, t) = build_state_typed(eval_exp(arg, ctx)).unwrap();
-					RunVal::State(s.extract(gate), t)
+					let val = eval_exp(arg, ctx);
+					let t = get_val_type(&val);
+					ctx.backend().apply(val, gate, t, ctx.config()).unwrap()
+				},
+				RunVal::Channel(kraus) => {
+					let val = eval_exp(arg, ctx);
+					let t = get_val_type(&val);
+					ctx.backend().apply_channel(val, &kraus, t).unwrap()
 				},
 				val => {
 					let msg = &format!("Cannot invoke {}", val);
-					let state = build_state(eval_exp(arg, ctx));
-					let gate = build_gate(&val, ctx).expect(msg);
-					RunVal::State(state.extract(gate), Type::Any /* TODO infer output type from `target` */)
+					let arg = eval_exp(arg, ctx);
+					if let RunVal::Tuple(ref fns) = val {
+						if let Some(ops) = build_tuple_ops(fns) {
+							let t = tuple_ops_type(fns, &mut ops.iter(), &get_val_type(&arg));
+							return ctx.backend().apply_tuple(arg, ops, t, ctx.config()).unwrap()
+						}
+					}
+					if let Some(gate) = build_gate(&val) {
+						ctx.backend().apply(arg, gate, Type::Any, ctx.config()).unwrap()
+					}
+					else {
+						let kraus = build_channel(&val).expect(msg);
+						ctx.backend().apply_channel(arg, &kraus, Type::Any).unwrap()
+					}
 				},
 			}
 		},
@@ -227,28 +424,48 @@ pub fn eval_exp(exp: &Exp, ctx: &Context) -> RunVal {
 			let val = eval_exp(&exp, ctx);
 			RunVal::Tuple((0..n).map(|_| val.clone()).collect())
 		},
-		&Exp::State(ref arg) => {
+		&Exp::State(ref arg) => ctx.backend().prepare(eval_exp(arg, ctx)).unwrap(),
+		&Exp::Scale(ref amp, ref arg) => {
+			let amp = eval_exp(amp, ctx);
+			let w = build_complex(&amp).ok_or_else(|| Error(format!("Not a number: {}", amp))).unwrap();
 			let (s, t) = build_state_typed(eval_exp(arg, ctx)).unwrap();
-			RunVal::State(s, t)
+			RunVal::State(s.into_iter().map(|x| x * w).collect(), t)
 		},
-		&Exp::Phase(phase, ref arg) => {
-			let val = eval_exp(arg, ctx);
-			build_gate(&val, ctx)
-				.map(|g| RunVal::Gate(g.power(phase)))
-				.unwrap_or_else(|| {
-					let (s, t) = build_state_typed(val).unwrap();
-					RunVal::State(s.phase(phase), t)
-				})
-		},
-		&Exp::Extract(ref arg, ref cases) => {
-            let state = build_state(eval_exp(arg, ctx));
-            let (gate, gt) = create_extract_gate_typed(cases, state.len(), ctx);
-            RunVal::State(state.extract(gate), gt)
+		&Exp::Phase(ref phase, ref arg) => {
+			let phase = build_phase(&eval_exp(phase, ctx)).unwrap();
+			apply_phase(eval_exp(arg, ctx), phase, ctx).unwrap()
 		},
+		&Exp::Extract(ref arg, ref cases) => eval_extract(eval_exp(arg, ctx), cases, ctx).unwrap(),
 		&Exp::Anno(ref exp, ref anno) => eval_type(anno, ctx.types()).unwrap().assign(eval_exp(exp, ctx)).unwrap(),
 	}
 }
 
+// Apply the gate given by the cases of an `extract` expression, padded to the size of the state
+fn eval_extract(val: RunVal, cases: &[Case], ctx: &Context) -> Ret<RunVal> {
+	let size = match val {
+		RunVal::Stabilizer(ref tableau, _) if tableau.qubits > MAX_DENSE_QUBITS => {
+			err!("Cannot extract from {} qubits without the stabilizer tableau (use a Clifford gate function instead)", tableau.qubits)
+		},
+		ref val => state_size(val),
+	}?;
+	let (gate, gt) = create_extract_gate_typed(cases, size, ctx)?;
+	ctx.backend().apply(val, Op::from_gate(gate), gt, ctx.config())
+}
+
+// Classically controlled ensemble of both branches of a conditional on a mixed state
+fn eval_mixed_cond(val: RunVal, then_exp: &Exp, else_exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (rho, _) = build_density_typed(val)?;
+	let probs = rho.diagonal();
+	if probs.len() > 2 {
+		return err!("Conditional state cannot be {}-dimensional", probs.len())
+	}
+	let parts = [else_exp, then_exp].iter().zip(probs)
+		.filter(|&(_, p)| p > 0_f64)
+		.map(|(e, p)| Ok((p, build_density_typed(eval_exp(e, ctx))?.0)))
+		.collect::<Ret<_>>()?;
+	Ok(RunVal::Density(Density::mix(parts), Type::Any /* TODO determine from then/else types */))
+}
+
 pub fn eval_exp_inline(exp: &Exp, ctx: &mut Context) -> RunVal {
 	match exp {
 		Exp::Scope(ref decls, ref exp) => {
@@ -280,19 +497,14 @@ pub fn eval_decl(decl: &Decl, ctx: &mut Context) -> Ret {
 			ctx.add_type(id.clone(), ty)
 		},
 		&Decl::Data(ref id, ref variants) => ctx.add_datatype(id.clone(), variants.clone()),
-		&Decl::Assert(ref expect, ref result) => {
+		&Decl::Assert(ref expect, ref result, compare, tolerance) => {
 			let a = eval_exp(expect, ctx);
 			let b = eval_exp(result, ctx);
-			let eq = match (&a, &b) {
-				(&RunVal::State(ref a, _), &RunVal::State(ref b, _)) => {
-					a.iter().zip(b).map(|(a, b)| {
-						let abs = (a - b).norm();
-						abs * abs
-					}).sum::<f32>() < 0.00001_f32
-				},
-				(a, b) => a == b,
+			let op = match compare {
+				Compare::Equal => "!=",
+				Compare::Similar => "!~=",
 			};
-			if !eq {err!("Assertion failed: {} != {}", a, b)}
+			if !values_match(&a, &b, compare, tolerance.unwrap_or(TOLERANCE))? {err!("Assertion failed: {} {} {}", a, op, b)}
 			else {Ok(())}
 		},
 		&Decl::Print(ref exp) => Ok(println!(":: {}", eval_exp(exp, ctx))),
@@ -303,6 +515,106 @@ pub fn eval_decl(decl: &Decl, ctx: &mut Context) -> Ret {
 	}
 }
 
+// Default tolerance of `assert`
+pub const TOLERANCE: Real = 0.00001;
+
+// Squared distance between states (after aligning their global phase, if requested)
+fn state_distance(a: &State, b: &State, compare: Compare) -> Real {
+	let len = ::std::cmp::max(a.len(), b.len());
+	let (a, b) = (a.clone().pad(len), b.clone().pad(len));
+	let phase = match compare {
+		Compare::Equal => Cf::new(1.0, 0.0),
+		Compare::Similar => {
+			let n = overlap(&b, &a);
+			if n.norm() > 0.0 {n / n.norm()} else {Cf::new(1.0, 0.0)}
+		},
+	};
+	a.into_iter().zip(b).map(|(a, b)| (a - b * phase).norm_sqr()).sum()
+}
+
+// Squared distance between states with norms `aa`, `bb` and overlap `ab` (see `state_distance`)
+fn overlap_distance(aa: Real, bb: Real, ab: Cf, compare: Compare) -> Real {
+	let cross = match compare {
+		Compare::Equal => ab.re,
+		Compare::Similar => ab.norm(),
+	};
+	aa + bb - 2.0 * cross
+}
+
+fn mps_distance(a: &Mps, b: &Mps, compare: Compare) -> Real {
+	overlap_distance(a.overlap(a).re, b.overlap(b).re, a.overlap(b), compare)
+}
+
+fn sparse_distance(a: &SparseState, b: &SparseState, compare: Compare) -> Real {
+	overlap_distance(a.prob_sum(), b.prob_sum(), sparse_overlap(a, b), compare)
+}
+
+pub fn values_eq(a: &RunVal, b: &RunVal) -> Ret<bool> {
+	values_match(a, b, Compare::Equal, TOLERANCE)
+}
+
+// Compare values, allowing for rounding error in states and numbers
+pub fn values_match(a: &RunVal, b: &RunVal, compare: Compare, tolerance: Real) -> Ret<bool> {
+	Ok(match (a, b) {
+		(&RunVal::Tuple(ref a), &RunVal::Tuple(ref b)) if a.len() == b.len() => {
+			for (a, b) in a.iter().zip(b) {
+				if !values_match(a, b, compare, tolerance)? {
+					return Ok(false)
+				}
+			}
+			true
+		},
+		(&RunVal::Real(a), &RunVal::Real(b)) => (a - b).abs() < tolerance,
+		(&RunVal::Real(a), &RunVal::Index(b)) | (&RunVal::Index(b), &RunVal::Real(a)) => (a - b as Real).abs() < tolerance,
+		(&RunVal::Complex(_), _) | (_, &RunVal::Complex(_)) => match (build_complex(a), build_complex(b)) {
+			(Some(a), Some(b)) => match compare {
+				Compare::Equal => (a - b).norm() < tolerance,
+				Compare::Similar => (a.norm() - b.norm()).abs() < tolerance,
+			},
+			_ => false,
+		},
+		(&RunVal::State(ref a, _), &RunVal::State(ref b, _)) => state_distance(a, b, compare) < tolerance,
+		(&RunVal::Mps(_, _), _) | (_, &RunVal::Mps(_, _)) => match (build_mps(a, 1), build_mps(b, 1)) {
+			(Some((a, _)), Some((b, _))) if a.dims() == b.dims() => mps_distance(&a, &b, compare) < tolerance,
+			_ => {
+				let (a, at) = build_state_typed(a.clone())?;
+				let (b, bt) = build_state_typed(b.clone())?;
+				values_match(&RunVal::State(a, at), &RunVal::State(b, bt), compare, tolerance)?
+			},
+		},
+		// Stabilizer states are only tracked up to global phase
+		(&RunVal::Stabilizer(_, _), _) | (_, &RunVal::Stabilizer(_, _)) => match (build_tableau(a), build_tableau(b)) {
+			(Some((a, _)), Some((b, _))) => a.same_state(&b),
+			_ => {
+				let (a, at) = build_state_typed(a.clone())?;
+				let (b, bt) = build_state_typed(b.clone())?;
+				values_match(&RunVal::State(a, at), &RunVal::State(b, bt), Compare::Similar, tolerance)?
+			},
+		},
+		(a, b) if is_mixed(a) || is_mixed(b) => {
+			let (a, _) = build_density_typed(a.clone())?;
+			let (b, _) = build_density_typed(b.clone())?;
+			a.0.iter().zip(b.0).flat_map(|(a, b)| a.iter().zip(b).map(|(a, b)| {
+				(a - b).norm_sqr()
+			}).collect::<Vec<_>>()).sum::<Real>() < tolerance
+		},
+		(&RunVal::Sparse(_, _), _) | (_, &RunVal::Sparse(_, _)) => match (build_sparse(a), build_sparse(b)) {
+			(Some((a, _)), Some((b, _))) => sparse_distance(&a, &b, compare) < tolerance,
+			_ => {
+				let (a, at) = build_state_typed(a.clone())?;
+				let (b, bt) = build_state_typed(b.clone())?;
+				values_match(&RunVal::State(a, at), &RunVal::State(b, bt), compare, tolerance)?
+			},
+		},
+		// Compare a state with a basis value (or a tuple of states and basis values)
+		(&RunVal::State(_, _), _) | (_, &RunVal::State(_, _)) => match (build_state_typed(a.clone()), build_state_typed(b.clone())) {
+			(Ok((a, _)), Ok((b, _))) => state_distance(&a, &b, compare) < tolerance,
+			_ => false,
+		},
+		(a, b) => a == b,
+	})
+}
+
 // TODO combine logic with eval_static::assign_pat_type()
 pub fn assign_pat(pat: &Pat, val: &RunVal, ctx: &mut Context) -> Ret {
 	match (pat, val) {
@@ -324,13 +636,30 @@ pub fn assign_pat(pat: &Pat, val: &RunVal, ctx: &mut Context) -> Ret {
 pub fn get_val_type(val: &RunVal) -> Type {
 	match val {
 		&RunVal::Index(_) => Type::Any,
+		&RunVal::Real(_) => Type::Any,
+		&RunVal::Complex(_) => Type::Any,
 		&RunVal::String(_) => Type::Any,
 		&RunVal::Data(ref dt, _) => Type::Data((*dt).clone()),
 		&RunVal::Tuple(ref vals) => Type::Tuple(vals.iter().map(get_val_type).collect()),
 		&RunVal::Func(_, _, _, ref ty) => ty.clone(),
 		&RunVal::Macro(_) => Type::Any, // TODO
 		&RunVal::State(_, ref ty) => ty.clone(),
+		&RunVal::Sparse(_, ref ty) => ty.clone(),
+		&RunVal::Density(_, ref ty) => ty.clone(),
+		&RunVal::Stabilizer(_, ref ty) => ty.clone(),
+		&RunVal::Mps(_, ref ty) => ty.clone(),
 		&RunVal::Gate(_) => Type::Any, // TODO
+		&RunVal::Channel(_) => Type::Any, // TODO
+		&RunVal::Observable(_) => Type::Any,
+	}
+}
+
+pub fn build_complex(val: &RunVal) -> Option<Cf> {
+	match val {
+		&RunVal::Index(n) => Some(Cf::new(n as Real, 0.0)),
+		&RunVal::Real(n) => Some(Cf::new(n, 0.0)),
+		&RunVal::Complex(n) => Some(n),
+		_ => None,
 	}
 }
 
@@ -357,28 +686,228 @@ pub fn build_state_typed(val: RunVal) -> Ret<(State, Type)> {
 			Ok((states.into_iter().fold(get_state(0), |a, (b, _)| State::combine(a, b)), ty))
 		},
 		RunVal::State(state, ty) => Ok((state, ty)),
+		RunVal::Sparse(state, ty) => Ok((state.to_dense(), ty)),
+		RunVal::Stabilizer(tableau, ty) => {
+			if tableau.qubits > MAX_DENSE_QUBITS {
+				return err!("Cannot simulate {} qubits without the stabilizer tableau", tableau.qubits)
+			}
+			Ok((tableau.to_state(), ty))
+		},
+		RunVal::Mps(mps, ty) => Ok((mps.to_state(), ty)),
+		RunVal::Density(_, _) => err!("Cannot build pure state from mixed state {}", val),
 		val => err!("Cannot build state from {}", val)
 	}
 }
 
-pub fn eval_gate_body(exp: &Exp, ctx: &Context) -> Option<Gate> {
+// Number of basis states of a value, without building the state vector of a sparse, stabilizer or matrix product state
+pub fn state_size(val: &RunVal) -> Ret<usize> {
+	match val {
+		&RunVal::Density(ref rho, _) => Ok(rho.size()),
+		&RunVal::Sparse(ref state, _) => Ok(state.size),
+		&RunVal::Stabilizer(ref tableau, _) => 1_usize.checked_shl(tableau.qubits as u32)
+			.ok_or_else(|| Error(format!("Too many qubits: {}", tableau.qubits))),
+		&RunVal::Mps(ref mps, _) => Ok(mps.dims().iter().product()),
+		val => Ok(build_state_typed(val.clone())?.0.len()),
+	}
+}
+
+pub fn build_density_typed(val: RunVal) -> Ret<(Density, Type)> {
+	match val {
+		RunVal::Density(rho, ty) => Ok((rho, ty)),
+		val => build_state_typed(val).map(|(s, t)| (Density::from_state(&s), t)),
+	}
+}
+
+pub fn is_mixed(val: &RunVal) -> bool {
+	match val {
+		&RunVal::Density(_, _) => true,
+		&RunVal::Tuple(ref vals) => vals.iter().any(is_mixed),
+		_ => false,
+	}
+}
+
+// Tableau for a stabilizer state or a tuple of `Bool` values
+pub fn build_tableau(val: &RunVal) -> Option<(Tableau, Type)> {
+	fn build_bits(val: &RunVal, bits: &mut Vec<bool>) -> Option<()> {
+		match val {
+			&RunVal::Data(ref dt, index) if dt.variants.len() == 2 => {
+				bits.push(index == 1);
+				Some(())
+			},
+			&RunVal::Tuple(ref vals) => vals.iter().map(|val| build_bits(val, bits)).collect(),
+			_ => None,
+		}
+	}
+	match val {
+		&RunVal::Stabilizer(ref tableau, ref ty) => Some((tableau.clone(), ty.clone())),
+		val => {
+			let mut bits = vec![];
+			build_bits(val, &mut bits)?;
+			Some((Tableau::from_bits(&bits), get_val_type(val)))
+		},
+	}
+}
+
+// Gates for each component of a (possibly nested) tuple of gates
+fn build_tuple_ops(fns: &[RunVal]) -> Option<Vec<Op>> {
+	let mut ops = vec![];
+	for f in fns {
+		match f {
+			&RunVal::Tuple(ref fns) => ops.extend(build_tuple_ops(fns)?),
+			f => ops.push(build_gate(f)?),
+		}
+	}
+	Some(ops)
+}
+
+// Output type of a tuple of gate functions: the result type of each function where known, and otherwise
+// the type of the matching input component (like gates, which keep the type of their input)
+fn tuple_ops_type(fns: &[RunVal], ops: &mut dyn Iterator<Item = &Op>, ty: &Type) -> Type {
+	let types = match ty {
+		&Type::Tuple(ref types) if types.len() == fns.len() => types.clone(),
+		_ => vec![Type::Any; fns.len()],
+	};
+	Type::Tuple(fns.iter().zip(types).map(|(f, t)| match f {
+		&RunVal::Tuple(ref fns) => tuple_ops_type(fns, ops, &t),
+		f => {
+			let op = ops.next();
+			let width = op.map(|op| op.width());
+			match get_val_type(f) {
+				Type::Func(_, ref ret) if ret.size().is_some() && ret.size() == width => (**ret).clone(),
+				_ if op.map_or(false, |op| op.width() == op.len()) && t.size() == width => t,
+				_ => Type::Any,
+			}
+		},
+	}).collect())
+}
+
+// Matrix product state for a tuple of basis values and matrix product states
+pub fn build_mps(val: &RunVal, bond_cap: usize) -> Option<(Mps, Type)> {
+	match val {
+		&RunVal::Mps(ref mps, ref ty) => Some((mps.clone(), ty.clone())),
+		&RunVal::Data(ref dt, index) => Some((Mps::from_basis(&[dt.variants.len()], &[index], bond_cap), Type::Data(dt.clone()))),
+		&RunVal::Tuple(ref vals) if !vals.is_empty() => {
+			let parts = vals.iter().map(|val| build_mps(val, bond_cap)).collect::<Option<Vec<_>>>()?;
+			let ty = Type::Tuple(parts.iter().map(|&(_, ref t)| t.clone()).collect());
+			let mut parts = parts.into_iter().map(|(mps, _)| mps);
+			let first = parts.next()?;
+			Some((parts.fold(first, Mps::combine), ty))
+		},
+		_ => None,
+	}
+}
+
+// Sparse state for a basis value, a tuple of basis values and sparse states, or a sparse state
+pub fn build_sparse(val: &RunVal) -> Option<(SparseState, Type)> {
+	match val {
+		&RunVal::Sparse(ref state, ref ty) => Some((state.clone(), ty.clone())),
+		&RunVal::Index(n) => Some((SparseState::basis(n + 1, n), Type::Any)),
+		&RunVal::Data(ref dt, index) => Some((SparseState::basis(dt.variants.len(), index), Type::Data(dt.clone()))),
+		&RunVal::Tuple(ref vals) => {
+			let parts = vals.iter().map(build_sparse).collect::<Option<Vec<_>>>()?;
+			let ty = Type::Tuple(parts.iter().map(|&(_, ref t)| t.clone()).collect());
+			Some((parts.into_iter().fold(SparseState::basis(1, 0), |a, (b, _)| a.combine(b)), ty))
+		},
+		_ => None,
+	}
+}
+
+// Store a pure state sparsely when it is large and mostly zero
+pub fn choose_state(state: SparseState, ty: Type) -> RunVal {
+	let state = state.prune();
+	if state.size >= SPARSE_MIN_SIZE && state.density() <= SPARSE_MAX_DENSITY {
+		RunVal::Sparse(state, ty)
+	}
+	else {RunVal::State(state.to_dense(), ty)}
+}
+
+// Raise a gate to the given power, or apply the phase to a state
+fn apply_phase(val: RunVal, phase: Phase, ctx: &Context) -> Ret<RunVal> {
+	if let Some(g) = build_gate(&val) {
+		return Ok(RunVal::Gate(g.power(phase)?))
+	}
+	ctx.backend().phase(val, phase)
+}
+
+pub fn eval_gate_body(exp: &Exp, ctx: &Context) -> Option<Op> {
 	match exp {
-		&Exp::Extract(ref _arg, ref cases) => Some(create_extract_gate_typed(cases, 0, ctx).0),
+		&Exp::Extract(ref _arg, ref cases) => Some(Op::from_gate(create_extract_gate_typed(cases, 0, ctx).unwrap().0)),
 		_ => None,
 	}
 }
 
-pub fn build_gate(val: &RunVal, ctx: &Context) -> Option<Gate> {
+pub fn build_bool_val(b: bool, ctx: &Context) -> Ret<RunVal> {
+	ctx.find_type(&"Bool".to_string())?.from_index(b as usize)
+}
+
+pub fn build_gate(val: &RunVal) -> Option<Op> {
 	match val {
 		&RunVal::Tuple(ref vals) => vals.iter()
-			.fold(Some(vec![get_state(0)]), 
-				|a, b| a.and_then(|a| build_gate(b, ctx).map(|b| a.combine(b)))),
+			.try_fold(Op::Diag(get_state(0)), |a, b| build_gate(b).map(|b| a.combine(b))),
 		&RunVal::Func(ref fn_ctx, ref _pat, ref body, ref _ty) => eval_gate_body(body, fn_ctx), // TODO use type
 		&RunVal::Gate(ref gate) => Some(gate.clone()),
+		&RunVal::Observable(ref obs) => Some(Op::from_gate(obs.to_gate())),
 		_ => None,
 	}
 }
 
+// Pauli string (such as "XZ" or `(X, Z)`), with tuples of weighted terms `(w, p)` denoting sums
+pub fn build_observable(val: &RunVal) -> Ret<Observable> {
+	let weighted = |val: &RunVal| match val {
+		&RunVal::Tuple(ref vals) => vals.len() == 2 && build_real(&vals[0]).is_ok(),
+		_ => false,
+	};
+	match val {
+		&RunVal::Observable(ref obs) => Ok(obs.clone()),
+		&RunVal::String(ref s) => Observable::parse(s),
+		&RunVal::Data(ref dt, index) => Observable::parse(&dt.variants[index]),
+		&RunVal::Tuple(ref vals) if weighted(val) => Ok(build_observable(&vals[1])?.scale(build_real(&vals[0])?)),
+		&RunVal::Tuple(ref vals) if !vals.is_empty() && vals.iter().all(weighted) => vals.iter()
+			.try_fold(Observable {terms: vec![]}, |a, b| a.plus(build_observable(b)?)),
+		&RunVal::Tuple(ref vals) => vals.iter()
+			.try_fold(Observable::identity(), |a, b| Ok(a.tensor(&build_observable(b)?))),
+		_ => err!("Not an observable: {}", val),
+	}
+}
+
+// Gates are treated as single-operator channels
+pub fn build_channel(val: &RunVal) -> Option<Channel> {
+	match val {
+		&RunVal::Channel(ref kraus) => Some(kraus.clone()),
+		&RunVal::Tuple(ref vals) => vals.iter()
+			.try_fold(vec![vec![get_state(0)]], |a, b| build_channel(b).map(|b| combine_channels(&a, &b))),
+		val => build_gate(val).map(|g| vec![g.to_gate()]),
+	}
+}
+
+// Real number given as a number or a fraction `(n, d)`
+pub fn build_real(val: &RunVal) -> Ret<Real> {
+	match val {
+		&RunVal::Index(n) => Ok(n as Real),
+		&RunVal::Real(n) => Ok(n),
+		&RunVal::Tuple(ref args) => match args[..] {
+			[RunVal::Index(n), RunVal::Index(d)] if d > 0 => Ok(n as Real / d as Real),
+			_ => err!("Invalid number: {}", val),
+		},
+		_ => err!("Invalid number: {}", val),
+	}
+}
+
+// Phase (in half-turns) given as a real or complex number or a fraction `(n, d)`
+pub fn build_phase(val: &RunVal) -> Ret<Phase> {
+	match val {
+		&RunVal::Complex(n) => Ok(n),
+		val => build_real(val).map(|n| Phase::new(n, 0.0)).map_err(|_| Error(format!("Invalid phase: {}", val))),
+	}
+}
+
+// Probability given as a number or a fraction `(n, d)`
+pub fn build_prob(val: &RunVal) -> Ret<Real> {
+	let p = build_real(val).map_err(|_| Error(format!("Invalid probability: {}", val)))?;
+	if !(0.0..=1.0).contains(&p) {err!("Probability out of range: {}", val)}
+	else {Ok(p)}
+}
+
 pub fn iterate_val(val: RunVal) -> Option<Vec<RunVal>> {
 	match val {
 		RunVal::Index(i) => {
@@ -389,7 +918,7 @@ pub fn iterate_val(val: RunVal) -> Option<Vec<RunVal>> {
 	}
 }
 
-pub fn create_extract_gate_typed(cases: &Vec<Case>, min_input_size: usize, ctx: &Context) -> (Gate, Type) {
+pub fn create_extract_gate_typed(cases: &[Case], min_input_size: usize, ctx: &Context) -> Ret<(Gate, Type)> {
 	fn reduce_type(output_type: Option<Type>, t: Type) -> Option<Type> {
 		Some(match output_type {
 			None => t,
@@ -397,14 +926,17 @@ pub fn create_extract_gate_typed(cases: &Vec<Case>, min_input_size: usize, ctx:
 		})
 	}
 	let mut dims: Gate = vec![];
+	// Cases contributing to each input dimension (for error reporting)
+	let mut sources: Vec<Vec<usize>> = vec![];
 	let mut output_type = None;
-	for case in cases.iter() {
+	for (c, case) in cases.iter().enumerate() {
 		match case {
 			&Case::Exp(ref selector, ref result) => {
 				let selector_state = build_state(eval_exp(selector, ctx));
-				let (result_state, result_type) = build_state_typed(eval_exp(result, ctx)).unwrap();
+				let (result_state, result_type) = build_state_typed(eval_exp(result, ctx))?;
 				while dims.len() < selector_state.len() || dims.len() < min_input_size {
 					dims.push(vec![]);
+					sources.push(vec![]);
 				}
 				for (i, s) in selector_state.iter().enumerate() {
 					let len = ::std::cmp::max(result_state.len(), dims[i].len());
@@ -413,15 +945,19 @@ pub fn create_extract_gate_typed(cases: &Vec<Case>, min_input_size: usize, ctx:
 						.zip(dims[i].clone().pad(len).into_iter())
 						.map(|(r, d)| r * s + d)
 						.collect();
+					if s.norm() > 0_f64 {
+						sources[i].push(c);
+					}
 				}
 				output_type = reduce_type(output_type, result_type);
 			},
 			&Case::Default(ref result) => {
-				let (state, result_type) = build_state_typed(eval_exp(result, ctx)).unwrap();
+				let (state, result_type) = build_state_typed(eval_exp(result, ctx))?;
 				for i in 0..dims.len() {
 					use num::Zero;
 					if dims[i].prob_sum().is_zero() {
 						dims[i] = state.clone();
+						sources[i].push(c);
 					}
 				}
 				output_type = reduce_type(output_type, result_type);
@@ -430,8 +966,18 @@ pub fn create_extract_gate_typed(cases: &Vec<Case>, min_input_size: usize, ctx:
 	}
 	let max_len = dims.iter().map(Vec::len).max().unwrap_or(0);
 	let gate: Gate = dims.into_iter().map(|s| s.pad(max_len)).collect();
-	// if !gate.is_unitary() {
-	// 	panic!("Non-unitary extraction: {:?}", cases);
-	// }
-	(gate, output_type.unwrap_or(Type::Any))
+	if ctx.config().strict {
+		let inputs = non_orthonormal_inputs(&gate);
+		if !inputs.is_empty() {
+			let mut offending = inputs.iter().flat_map(|&i| sources[i].clone()).collect::<Vec<_>>();
+			offending.sort();
+			offending.dedup();
+			let names = offending.into_iter().map(|c| match cases[c] {
+				Case::Exp(ref selector, _) => format!("{}", eval_exp(selector, ctx)),
+				Case::Default(_) => "_".to_string(),
+			}).chain(inputs.iter().filter(|&&i| sources[i].is_empty()).map(|i| format!("<no case for input {}>", i)));
+			return err!("Non-unitary extraction (cases: {})", names.collect::<Vec<_>>().join(", "))
+		}
+	}
+	Ok((gate, output_type.unwrap_or(Type::Any)))
 }
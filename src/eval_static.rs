@@ -75,6 +75,8 @@ pub fn eval_type(pat: &Pat, ctx: &TypeContext) -> Ret<Type> {
 pub fn infer_type(exp: &Exp, ctx: &TypeContext) -> Ret<Type> {
 	Ok(match exp {
 		&Exp::Index(_) => Type::Any,
+		&Exp::Real(_) => Type::Any,
+		&Exp::Complex(_) => Type::Any,
 		&Exp::String(_) => Type::Any,
 		&Exp::Var(ref id) => ctx.find_var_type(id)?,
 		&Exp::Scope(ref decls, ref ret) => {
@@ -111,6 +113,7 @@ pub fn infer_type(exp: &Exp, ctx: &TypeContext) -> Ret<Type> {
 			Type::Tuple((0..n).map(|_| ty.clone()).collect())
 		},
 		&Exp::State(ref arg) => infer_type(arg, ctx)?,
+		&Exp::Scale(_, ref arg) => infer_type(arg, ctx)?,
 		&Exp::Phase(_, ref arg) => infer_type(arg, ctx)?,
 		&Exp::Extract(ref _arg, ref cases) => {
 			cases.iter()
@@ -4,8 +4,6 @@ extern crate reqwest;
 extern crate num;
 #[macro_use]
 extern crate nom;
-extern crate lapacke;
-extern crate lapack_src;
 
 #[macro_use]
 pub mod error;
@@ -13,6 +11,14 @@ pub mod resource;
 pub mod ast;
 pub mod types;
 pub mod engine;
+pub mod linalg;
+pub mod sparse;
+pub mod structured;
+pub mod stabilizer;
+pub mod observable;
+pub mod mps;
+pub mod backend;
+pub mod optimize;
 pub mod eval;
 pub mod eval_static;
 pub mod parser;
@@ -0,0 +1,485 @@
+use error::*;
+use engine::*;
+
+use num::{Zero, One, ToPrimitive};
+use num::complex::Complex;
+
+// Row-major complex matrix (`m[i][j]` is row `i`, column `j`)
+pub type Matrix = Vec<Vec<Cf>>;
+
+const MAX_SWEEPS: usize = 100;
+// Degree of the Padé approximant used by `expm`
+const PADE_DEGREE: usize = 6;
+// Terms of the series for log(I + x) once |x| <= 1/4
+const LOG_TERMS: usize = 30;
+
+pub fn identity(n: usize) -> Matrix {
+	(0..n).map(|i| (0..n).map(|j| if i == j {Complex::one()} else {Complex::zero()}).collect()).collect()
+}
+
+pub fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+	let inner = b.len();
+	let cols = b.first().map(|r| r.len()).unwrap_or(0);
+	a.iter().map(|row| (0..cols).map(|j| {
+		(0..inner).fold(Complex::zero(), |sum, k| sum + row[k] * b[k][j])
+	}).collect()).collect()
+}
+
+pub fn adjoint(m: &Matrix) -> Matrix {
+	let cols = m.first().map(|r| r.len()).unwrap_or(0);
+	(0..cols).map(|j| m.iter().map(|row| row[j].conj()).collect()).collect()
+}
+
+// Eigenvalues and orthonormal eigenvectors of a Hermitian matrix (cyclic Jacobi method)
+// Returns the eigenvalues in ascending order along with the matching eigenvectors
+pub fn eigh(m: &Matrix) -> (Vec<Real>, Vec<State>) {
+	let n = m.len();
+	let mut a = m.clone();
+	let mut v = identity(n);
+	let tolerance = Real::EPSILON * Real::EPSILON;
+	for _ in 0..MAX_SWEEPS {
+		let off = (0..n).fold(0.0, |sum, i| (0..n).fold(sum, |sum, j| if i == j {sum} else {sum + a[i][j].norm_sqr()}));
+		let total = (0..n).fold(off, |sum, i| sum + a[i][i].norm_sqr());
+		if off <= tolerance * total {
+			break
+		}
+		for p in 0..n {
+			for q in p + 1..n {
+				let b = a[p][q];
+				if b.norm() <= Real::MIN_POSITIVE {
+					continue
+				}
+				// Rotate the phase of row/column `q` so that `a[p][q]` becomes real
+				let w = (b / b.norm()).conj();
+				for k in 0..n {
+					a[k][q] *= w;
+					v[k][q] *= w;
+				}
+				for x in a[q].iter_mut() {
+					*x *= w.conj();
+				}
+				// Real Jacobi rotation zeroing `a[p][q]`
+				let apq = a[p][q].re;
+				let theta = (a[q][q].re - a[p][p].re) / (apq + apq);
+				let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+				let c = 1.0 / (t * t + 1.0).sqrt();
+				let s = t * c;
+				for k in 0..n {
+					let (x, y) = (a[k][p], a[k][q]);
+					a[k][p] = x * c - y * s;
+					a[k][q] = x * s + y * c;
+					let (x, y) = (v[k][p], v[k][q]);
+					v[k][p] = x * c - y * s;
+					v[k][q] = x * s + y * c;
+				}
+				let (top, bottom) = a.split_at_mut(q);
+				for (rp, rq) in top[p].iter_mut().zip(bottom[0].iter_mut()) {
+					let (x, y) = (*rp, *rq);
+					*rp = x * c - y * s;
+					*rq = x * s + y * c;
+				}
+			}
+		}
+	}
+	let mut order = (0..n).collect::<Vec<_>>();
+	order.sort_by(|&i, &j| a[i][i].re.partial_cmp(&a[j][j].re).unwrap());
+	(order.iter().map(|&i| a[i][i].re).collect(), order.iter().map(|&i| v.iter().map(|row| row[i]).collect()).collect())
+}
+
+// Apply a function to the eigenvalues of a Hermitian matrix
+pub fn hermitian_map<F>(m: &Matrix, f: F) -> Matrix
+where F: Fn(Real) -> Cf {
+	let (vals, vecs) = eigh(m);
+	let n = m.len();
+	(0..n).map(|i| (0..n).map(|j| {
+		vals.iter().zip(vecs.iter()).fold(Complex::zero(), |sum, (&x, v)| sum + f(x) * v[i] * v[j].conj())
+	}).collect()).collect()
+}
+
+// Thin singular value decomposition `m = u * diag(s) * vh`, keeping only the nonzero singular values (in descending order)
+// One-sided Jacobi rotations orthogonalize the columns directly, so the condition number is not squared as with `eigh(m m†)`
+pub fn svd(m: &Matrix) -> (Matrix, Vec<Real>, Matrix) {
+	let rows = m.len();
+	let cols = m.first().map(|r| r.len()).unwrap_or(0);
+	if cols > rows {
+		let (u, s, vh) = svd(&adjoint(m));
+		return (adjoint(&vh), s, adjoint(&u))
+	}
+	let mut a = m.clone();
+	let mut v = identity(cols);
+	for _ in 0..MAX_SWEEPS {
+		let mut rotated = false;
+		for p in 0..cols {
+			for q in p + 1..cols {
+				let alpha = a.iter().fold(0.0, |sum, row| sum + row[p].norm_sqr());
+				let beta = a.iter().fold(0.0, |sum, row| sum + row[q].norm_sqr());
+				let gamma = a.iter().fold(Cf::zero(), |sum, row| sum + row[p].conj() * row[q]);
+				if gamma.norm() <= Real::EPSILON * (alpha * beta).sqrt() || gamma.norm() <= Real::MIN_POSITIVE {
+					continue
+				}
+				rotated = true;
+				// Rotate the phase of column `q` so that its inner product with column `p` becomes real
+				let w = (gamma / gamma.norm()).conj();
+				for row in a.iter_mut().chain(v.iter_mut()) {
+					row[q] *= w;
+				}
+				// Real Jacobi rotation making the columns orthogonal
+				let g = gamma.norm();
+				let theta = (beta - alpha) / (g + g);
+				let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+				let c = 1.0 / (t * t + 1.0).sqrt();
+				let s = t * c;
+				for row in a.iter_mut().chain(v.iter_mut()) {
+					let (x, y) = (row[p], row[q]);
+					row[p] = x * c - y * s;
+					row[q] = x * s + y * c;
+				}
+			}
+		}
+		if !rotated {
+			break
+		}
+	}
+	let norms = (0..cols).map(|j| a.iter().fold(0.0, |sum, row| sum + row[j].norm_sqr()).sqrt()).collect::<Vec<_>>();
+	let max = norms.iter().cloned().fold(0.0, Real::max);
+	let tolerance = max * Real::EPSILON * 1000.0;
+	let mut order = (0..cols).filter(|&j| norms[j] > tolerance).collect::<Vec<_>>();
+	order.sort_by(|&i, &j| norms[j].partial_cmp(&norms[i]).unwrap());
+	let u = a.iter().map(|row| order.iter().map(|&j| row[j] / norms[j]).collect()).collect();
+	let s = order.iter().map(|&j| norms[j]).collect();
+	let vh = order.iter().map(|&j| v.iter().map(|row| row[j].conj()).collect()).collect();
+	(u, s, vh)
+}
+
+// Inverse of a square matrix (LU decomposition with partial pivoting)
+pub fn inverse(m: &Matrix) -> Ret<Matrix> {
+	let n = m.len();
+	let scale = m.iter().flat_map(|row| row.iter()).fold(0_f64, |a, x| a.max(x.norm()));
+	let mut a = m.clone();
+	let mut inv = identity(n);
+	for k in 0..n {
+		let p = (k..n).max_by(|&i, &j| a[i][k].norm().partial_cmp(&a[j][k].norm()).unwrap()).unwrap();
+		if a[p][k].norm() <= scale * Real::EPSILON * n as f64 {
+			return err!("Matrix is singular")
+		}
+		a.swap(k, p);
+		inv.swap(k, p);
+		let pivot = a[k][k];
+		let (pivot_row, inv_row) = (a[k].clone(), inv[k].clone());
+		for i in k + 1..n {
+			let f = a[i][k] / pivot;
+			if f.is_zero() {
+				continue
+			}
+			for (y, x) in a[i].iter_mut().zip(pivot_row.iter()).skip(k) {
+				*y -= f * x;
+			}
+			for (y, x) in inv[i].iter_mut().zip(inv_row.iter()) {
+				*y -= f * x;
+			}
+		}
+	}
+	// Back substitution on the upper triangular factor
+	for k in (0..n).rev() {
+		let pivot = a[k][k];
+		inv[k].iter_mut().for_each(|x| *x /= pivot);
+		let inv_row = inv[k].clone();
+		for i in 0..k {
+			let f = a[i][k];
+			for (y, x) in inv[i].iter_mut().zip(inv_row.iter()) {
+				*y -= f * x;
+			}
+		}
+	}
+	Ok(inv)
+}
+
+// Reduce to upper Hessenberg form `h = q† * m * q` (Householder reflections)
+fn hessenberg(m: &Matrix) -> (Matrix, Matrix) {
+	let n = m.len();
+	let mut h = m.clone();
+	let mut q = identity(n);
+	for k in 0..n.saturating_sub(2) {
+		let norm = (k + 1..n).fold(0.0, |sum, i| sum + h[i][k].norm_sqr()).sqrt();
+		if norm <= Real::MIN_POSITIVE {
+			continue
+		}
+		let x = h[k + 1][k];
+		let alpha = if x.norm() > 0.0 {-x / x.norm() * norm} else {Complex::new(-norm, 0.0)};
+		let mut v: State = (k + 1..n).map(|i| h[i][k]).collect();
+		v[0] -= alpha;
+		let vnorm = v.iter().fold(0.0, |sum, x| sum + x.norm_sqr()).sqrt();
+		if vnorm <= Real::MIN_POSITIVE {
+			continue
+		}
+		v.iter_mut().for_each(|x| *x /= vnorm);
+		let two = Complex::new(2.0, 0.0);
+		// h = (I - 2vv†) h (I - 2vv†)
+		let mut d = vec![Cf::zero(); n];
+		for (x, row) in v.iter().zip(h[k + 1..].iter()) {
+			for (dj, y) in d.iter_mut().zip(row.iter()) {
+				*dj += x.conj() * y;
+			}
+		}
+		for (x, row) in v.iter().zip(h[k + 1..].iter_mut()) {
+			for (y, dj) in row.iter_mut().zip(d.iter()) {
+				*y -= *x * dj * two;
+			}
+		}
+		for row in h.iter_mut().chain(q.iter_mut()) {
+			let d = v.iter().enumerate().fold(Cf::zero(), |sum, (j, x)| sum + row[k + 1 + j] * x) * two;
+			for (j, x) in v.iter().enumerate() {
+				row[k + 1 + j] -= d * x.conj();
+			}
+		}
+	}
+	(h, q)
+}
+
+// Complex Schur decomposition `m = q * t * q†` with `t` upper triangular (shifted QR iteration)
+pub fn schur(m: &Matrix) -> Ret<(Matrix, Matrix)> {
+	let n = m.len();
+	let (mut t, mut q) = hessenberg(m);
+	let mut hi = n;
+	let mut iterations = 0;
+	while hi > 1 {
+		// Deflate once the last subdiagonal entry of the active block vanishes
+		let mut lo = hi - 1;
+		while lo > 0 {
+			let scale = t[lo - 1][lo - 1].norm() + t[lo][lo].norm();
+			if t[lo][lo - 1].norm() <= Real::EPSILON * scale || t[lo][lo - 1].norm() <= Real::MIN_POSITIVE {
+				t[lo][lo - 1] = Complex::zero();
+				break
+			}
+			lo -= 1;
+		}
+		if lo == hi - 1 {
+			hi -= 1;
+			iterations = 0;
+			continue
+		}
+		iterations += 1;
+		if iterations > MAX_SWEEPS * 10 {
+			return err!("Schur decomposition did not converge")
+		}
+		// Wilkinson shift (eigenvalue of the trailing 2x2 block closest to its last entry)
+		let (a, b, c, d) = (t[hi - 2][hi - 2], t[hi - 2][hi - 1], t[hi - 1][hi - 2], t[hi - 1][hi - 1]);
+		let half = 0.5;
+		let disc = ((a - d) * (a - d) * half * half + b * c).sqrt();
+		let mean = (a + d) * half;
+		let mut shift = if (mean + disc - d).norm() < (mean - disc - d).norm() {mean + disc} else {mean - disc};
+		if iterations % 10 == 0 {
+			// Exceptional shift to escape cycles
+			shift = d + Complex::new(c.norm(), 0.0);
+		}
+		for (k, row) in t.iter_mut().enumerate().take(hi).skip(lo) {
+			row[k] -= shift;
+		}
+		let mut rotations = vec![];
+		for k in lo..hi - 1 {
+			let (x, y) = (t[k][k], t[k + 1][k]);
+			let r = (x.norm_sqr() + y.norm_sqr()).sqrt();
+			let (c, s) = if r > 0.0 {(x / r, y / r)} else {(Complex::one(), Complex::zero())};
+			let (top, bottom) = t.split_at_mut(k + 1);
+			for (rk, rl) in top[k].iter_mut().zip(bottom[0].iter_mut()).skip(k) {
+				let (x, y) = (*rk, *rl);
+				*rk = c.conj() * x + s.conj() * y;
+				*rl = c * y - s * x;
+			}
+			rotations.push((k, c, s));
+		}
+		for (k, c, s) in rotations {
+			for row in t.iter_mut().take(::std::cmp::min(k + 2, hi)) {
+				let (x, y) = (row[k], row[k + 1]);
+				row[k] = x * c + y * s;
+				row[k + 1] = y * c.conj() - x * s.conj();
+			}
+			for row in q.iter_mut() {
+				let (x, y) = (row[k], row[k + 1]);
+				row[k] = x * c + y * s;
+				row[k + 1] = y * c.conj() - x * s.conj();
+			}
+		}
+		for (k, row) in t.iter_mut().enumerate().take(hi).skip(lo) {
+			row[k] += shift;
+		}
+	}
+	for (i, row) in t.iter_mut().enumerate() {
+		row.iter_mut().take(i).for_each(|x| *x = Complex::zero());
+	}
+	Ok((q, t))
+}
+
+// Eigenvalues and eigenvectors (as the columns of the returned matrix) of a diagonalizable matrix
+pub fn eig(m: &Matrix) -> Ret<(Vec<Cf>, Matrix)> {
+	let n = m.len();
+	let (q, t) = schur(m)?;
+	let scale = t.iter().flat_map(|row| row.iter()).fold(0_f64, |a, x| a.max(x.norm()));
+	let tolerance = scale * Real::EPSILON.sqrt();
+	let mut y: Matrix = vec![vec![Complex::zero(); n]; n];
+	for k in 0..n {
+		y[k][k] = Complex::one();
+		// Back substitution for (t - t[k][k]) y = 0
+		for i in (0..k).rev() {
+			let sum = (i + 1..k + 1).fold(Cf::zero(), |sum, j| sum + t[i][j] * y[j][k]);
+			let diff = t[i][i] - t[k][k];
+			if diff.norm() > tolerance {
+				y[i][k] = -sum / diff;
+			}
+			else if sum.norm() > tolerance {
+				return err!("Matrix is not diagonalizable")
+			}
+		}
+	}
+	let mut vecs = mul(&q, &y);
+	for k in 0..n {
+		let norm = (0..n).fold(0.0, |sum, i| sum + vecs[i][k].norm_sqr()).sqrt();
+		for row in vecs.iter_mut() {
+			row[k] /= norm;
+		}
+	}
+	Ok(((0..n).map(|i| t[i][i]).collect(), vecs))
+}
+
+// Apply a function to the eigenvalues of a diagonalizable matrix
+pub fn eigen_map<F>(m: &Matrix, f: F) -> Ret<Matrix>
+where F: Fn(Cf) -> Cf {
+	let (vals, vecs) = eig(m)?;
+	let inv = inverse(&vecs)?;
+	let scaled = vecs.iter().map(|row| row.iter().zip(vals.iter()).map(|(x, &v)| *x * f(v)).collect()).collect();
+	Ok(mul(&scaled, &inv))
+}
+
+// Largest absolute row sum
+fn norm_inf(m: &Matrix) -> Real {
+	m.iter().map(|row| row.iter().fold(0.0, |sum, x| sum + x.norm())).fold(0.0, Real::max)
+}
+
+// Matrix exponential (scaling and squaring with a diagonal Padé approximant)
+pub fn expm(m: &Matrix) -> Ret<Matrix> {
+	let n = m.len();
+	let norm = norm_inf(m);
+	if !norm.is_finite() {
+		return err!("Exponential of a non-finite matrix")
+	}
+	// Halve the matrix until the approximant is accurate, then square the result back
+	let mut squarings = 0;
+	let mut scale = 1.0;
+	while norm * scale > 0.5 {
+		scale *= 0.5;
+		squarings += 1;
+	}
+	let a = m.iter().map(|row| row.iter().map(|&x| x * scale).collect()).collect();
+	let mut num = identity(n);
+	let mut den = identity(n);
+	let mut power = identity(n);
+	let mut c = 1.0;
+	for k in 1..PADE_DEGREE + 1 {
+		c *= ((PADE_DEGREE - k + 1) as f64) / ((k * (2 * PADE_DEGREE - k + 1)) as f64);
+		power = mul(&power, &a);
+		let sign = if k % 2 == 0 {c} else {-c};
+		for i in 0..n {
+			for j in 0..n {
+				num[i][j] += power[i][j] * c;
+				den[i][j] += power[i][j] * sign;
+			}
+		}
+	}
+	let mut e = mul(&inverse(&den)?, &num);
+	for _ in 0..squarings {
+		e = mul(&e, &e);
+	}
+	Ok(e)
+}
+
+// Principal square root of an upper triangular matrix (Björck–Hammarling recurrence)
+fn sqrt_triangular(t: &Matrix) -> Matrix {
+	let n = t.len();
+	let mut r = vec![vec![Complex::zero(); n]; n];
+	for j in 0..n {
+		r[j][j] = t[j][j].sqrt();
+		for i in (0..j).rev() {
+			let sum = (i + 1..j).fold(Cf::zero(), |sum, k| sum + r[i][k] * r[k][j]);
+			r[i][j] = (t[i][j] - sum) / (r[i][i] + r[j][j]);
+		}
+	}
+	r
+}
+
+// Principal logarithm of an upper triangular matrix (inverse scaling and squaring)
+pub fn log_triangular(t: &Matrix) -> Ret<Matrix> {
+	let n = t.len();
+	let scale = t.iter().flat_map(|row| row.iter()).fold(0_f64, |a, x| a.max(x.norm()));
+	if (0..n).any(|i| t[i][i].norm() <= scale * Real::EPSILON * n as f64) {
+		return err!("Logarithm of a singular matrix")
+	}
+	let mut x = t.clone();
+	for (i, row) in x.iter_mut().enumerate() {
+		row[i] = principal(row[i]);
+	}
+	// Take square roots until the matrix is close to the identity
+	let mut roots = 0;
+	while norm_inf(&(0..n).map(|i| (0..n).map(|j| if i == j {x[i][j] - Complex::one()} else {x[i][j]}).collect()).collect()) > 0.25 {
+		if roots > MAX_SWEEPS {
+			return err!("Matrix logarithm did not converge")
+		}
+		x = sqrt_triangular(&x);
+		roots += 1;
+	}
+	for (i, row) in x.iter_mut().enumerate() {
+		row[i] -= Complex::one();
+	}
+	// log(I + x) = x - x²/2 + x³/3 - ..., scaled back up by 2^roots
+	let mut log = vec![vec![Complex::zero(); n]; n];
+	let mut power = identity(n);
+	for k in 1..LOG_TERMS + 1 {
+		power = mul(&power, &x);
+		let c = 2_f64.powi(roots as i32) / k as f64;
+		let c = if k % 2 == 0 {-c} else {c};
+		for i in 0..n {
+			for j in 0..n {
+				log[i][j] += power[i][j] * c;
+			}
+		}
+	}
+	Ok(log)
+}
+
+// Principal matrix logarithm, computed on the Schur form so that defective matrices are supported
+pub fn logm(m: &Matrix) -> Ret<Matrix> {
+	let (q, t) = schur(m)?;
+	Ok(mul(&mul(&q, &log_triangular(&t)?), &adjoint(&q)))
+}
+
+// Principal power of a square matrix
+// Integer powers are repeated products, while other powers are taken on the Schur form
+pub fn powm(m: &Matrix, p: Cf) -> Ret<Matrix> {
+	let n = m.len();
+	if p.im.is_zero() && p.re.fract().is_zero() {
+		if let Some(mut k) = p.re.abs().to_u64() {
+			let mut base = if p.re < 0.0 {inverse(m)?} else {m.clone()};
+			let mut result = identity(n);
+			while k > 0 {
+				if k % 2 == 1 {
+					result = mul(&result, &base);
+				}
+				base = mul(&base, &base);
+				k /= 2;
+			}
+			return Ok(result)
+		}
+	}
+	let (q, t) = schur(m)?;
+	let scale = t.iter().flat_map(|row| row.iter()).fold(0_f64, |a, x| a.max(x.norm()));
+	let tolerance = scale * Real::EPSILON.sqrt();
+	if (0..n).any(|i| t[i][i].norm() <= tolerance) {
+		// Zero eigenvalues have no logarithm, so map them directly when there is an eigenbasis
+		if p.re <= 0.0 {
+			return err!("Cannot take a non-positive power of a singular gate")
+		}
+		return eigen_map(m, |x| if x.norm() <= tolerance {Complex::zero()} else {principal(x).powc(p)})
+	}
+	let log = log_triangular(&t)?;
+	let r = expm(&log.into_iter().map(|row| row.into_iter().map(|x| x * p).collect()).collect())?;
+	Ok(mul(&mul(&q, &r), &adjoint(&q)))
+}
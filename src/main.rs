@@ -4,7 +4,7 @@ extern crate rustyline;
 extern crate notify;
 extern crate funqy;
 
-use funqy::{parser, eval, stdlib};
+use funqy::{parser, eval, stdlib, stabilizer};
 
 use std::env;
 use std::fs;
@@ -19,14 +19,24 @@ fn main() {
 		(author: "Ryan Vandersmith (https://github.com/rvanasa)")
 		(about: "FunQy language command-line interface")
 		(@subcommand eval =>
-			(about: "evaluate script using ideal simulator")
+			(about: "evaluate script using state vector simulator")
 			(@arg filename: +required "input filename")
 			(@arg output: -o --output +takes_value "output filename")
 			(@arg watch: -w --watch "re-evaluate with optimizations on file change")
+			(@arg density: -d --density "simulate mixed states using density matrices")
+			(@arg strict: -s --strict "reject non-unitary extract gates")
+			(@arg seed: --seed +takes_value "seed for reproducible measurements")
+			(@arg stabilizer: --stabilizer conflicts_with[no_stabilizer] "always simulate Clifford gates on a stabilizer tableau")
+			(@arg no_stabilizer: --("no-stabilizer") "never use the stabilizer simulator")
+			(@arg mps: --mps +takes_value "simulate registers as matrix product states with the given bond dimension")
 		)
 		(@subcommand repl =>
 			(about: "begin REPL session")
 			(@arg history: -h --history +takes_value "history file")
+			(@arg seed: --seed +takes_value "seed for reproducible measurements")
+			(@arg stabilizer: --stabilizer conflicts_with[no_stabilizer] "always simulate Clifford gates on a stabilizer tableau")
+			(@arg no_stabilizer: --("no-stabilizer") "never use the stabilizer simulator")
+			(@arg mps: --mps +takes_value "simulate registers as matrix product states with the given bond dimension")
 		)
 	).get_matches();
 	
@@ -34,7 +44,25 @@ fn main() {
 		.expect("Could not find working directory")
 		.to_str().unwrap()).unwrap();
 	
+	let configure = |ctx: &mut eval::Context, matches: &clap::ArgMatches| {
+		if let Some(seed) = matches.value_of("seed") {
+			ctx.set_seed(seed.parse().expect("Invalid seed"));
+		}
+		if matches.is_present("stabilizer") {
+			ctx.config_mut().stabilizer = stabilizer::StabilizerMode::Always;
+		}
+		if matches.is_present("no_stabilizer") {
+			ctx.config_mut().stabilizer = stabilizer::StabilizerMode::Never;
+		}
+		if let Some(bond) = matches.value_of("mps") {
+			ctx.config_mut().set_bond_dim(bond.parse().expect("Invalid bond dimension")).unwrap();
+		}
+	};
+	
 	if let Some(matches) = matches.subcommand_matches("eval") {
+		configure(&mut ctx, matches);
+		ctx.config_mut().density = matches.is_present("density");
+		ctx.config_mut().strict = matches.is_present("strict");
 		let do_eval = |module: &eval::Module| {
 			let result = eval::eval_exp(&module.exp, &module.ctx);
 			println!(">> {}", result);
@@ -68,6 +96,7 @@ fn main() {
 		}
 	}
 	else if let Some(matches) = matches.subcommand_matches("repl") {
+		configure(&mut ctx, matches);
 		let mut rl = Editor::<()>::new();
 		let history = if matches.is_present("history") {
 			matches.value_of("history")
@@ -0,0 +1,261 @@
+use engine::*;
+use linalg::{Matrix, svd};
+
+use std::fmt;
+use rand::Rng;
+use num::Zero;
+
+// Tensor for one component of a matrix product state (`data[(l * dim + s) * right + r]`)
+#[derive(Clone,Debug,PartialEq)]
+pub struct Site {
+	pub left: usize,
+	pub dim: usize,
+	pub right: usize,
+	pub data: Vec<Cf>,
+}
+
+impl Site {
+	fn basis(dim: usize, index: usize) -> Site {
+		let mut data = vec![Cf::zero(); dim];
+		data[index] = Cf::new(1.0, 0.0);
+		Site {left: 1, dim, right: 1, data}
+	}
+
+	fn get(&self, l: usize, s: usize, r: usize) -> Cf {
+		self.data[(l * self.dim + s) * self.right + r]
+	}
+
+	// Matrix with rows `(l, s)` and columns `r`
+	fn left_matrix(&self) -> Matrix {
+		(0..self.left * self.dim).map(|i| self.data[i * self.right..(i + 1) * self.right].to_vec()).collect()
+	}
+
+	// Matrix with rows `l` and columns `(s, r)`
+	fn right_matrix(&self) -> Matrix {
+		let width = self.dim * self.right;
+		(0..self.left).map(|l| self.data[l * width..(l + 1) * width].to_vec()).collect()
+	}
+
+	fn from_matrix(left: usize, dim: usize, right: usize, m: &Matrix) -> Site {
+		Site {left, dim, right, data: m.iter().flat_map(|row| row.iter().cloned()).collect()}
+	}
+}
+
+// Matrix product state with a capped bond dimension (sites left of `center` are left-canonical, sites right of it right-canonical)
+#[derive(Clone,Debug,PartialEq)]
+pub struct Mps {
+	pub sites: Vec<Site>,
+	pub bond_cap: usize,
+	center: usize,
+	// Total weight discarded by truncation
+	pub truncation: Real,
+}
+
+impl Mps {
+	pub fn from_basis(dims: &[usize], indices: &[usize], bond_cap: usize) -> Mps {
+		Mps {
+			sites: dims.iter().zip(indices).map(|(&d, &i)| Site::basis(d, i)).collect(),
+			bond_cap,
+			center: 0,
+			truncation: 0.0,
+		}
+	}
+
+	pub fn dims(&self) -> Vec<usize> {
+		self.sites.iter().map(|s| s.dim).collect()
+	}
+
+	pub fn bond_dim(&self) -> usize {
+		self.sites.iter().map(|s| s.right).max().unwrap_or(1)
+	}
+
+	fn move_center(&mut self, k: usize) {
+		while self.center < k {
+			let c = self.center;
+			let (left, dim) = (self.sites[c].left, self.sites[c].dim);
+			let (u, s, vh) = svd(&self.sites[c].left_matrix());
+			self.sites[c] = Site::from_matrix(left, dim, s.len(), &u);
+			let next = &self.sites[c + 1];
+			let carry = (0..s.len()).map(|i| (0..next.dim * next.right).map(|j| {
+				(0..next.left).fold(Cf::zero(), |sum, m| sum + vh[i][m] * s[i] * next.data[m * next.dim * next.right + j])
+			}).collect()).collect::<Matrix>();
+			self.sites[c + 1] = Site::from_matrix(s.len(), next.dim, next.right, &carry);
+			self.center += 1;
+		}
+		while self.center > k {
+			let c = self.center;
+			let (dim, right) = (self.sites[c].dim, self.sites[c].right);
+			let (u, s, vh) = svd(&self.sites[c].right_matrix());
+			self.sites[c] = Site::from_matrix(s.len(), dim, right, &vh);
+			let prev = &self.sites[c - 1];
+			let carry = (0..prev.left * prev.dim).map(|i| (0..s.len()).map(|j| {
+				(0..prev.right).fold(Cf::zero(), |sum, m| sum + prev.data[i * prev.right + m] * u[m][j] * s[j])
+			}).collect()).collect::<Matrix>();
+			self.sites[c - 1] = Site::from_matrix(prev.left, prev.dim, s.len(), &carry);
+			self.center -= 1;
+		}
+	}
+
+	// Apply a gate to site `k` (`g[s][t]` is the amplitude of output `t` for input `s`),
+	// renormalizing like `apply_pair` so that non-unitary gates keep the canonical form
+	pub fn apply_local(&mut self, k: usize, g: &Gate) {
+		self.move_center(k);
+		let site = &self.sites[k];
+		let get = |s: usize, t: usize| g.get(s).and_then(|v| v.get(t)).cloned().unwrap_or(Cf::zero());
+		let mut data = vec![Cf::zero(); site.data.len()];
+		for l in 0..site.left {
+			for s in 0..site.dim {
+				for t in 0..site.dim {
+					let u = get(s, t);
+					if u.is_zero() {
+						continue
+					}
+					for r in 0..site.right {
+						data[(l * site.dim + t) * site.right + r] += u * site.get(l, s, r);
+					}
+				}
+			}
+		}
+		let norm = data.iter().map(|n| n.norm_sqr()).sum::<Real>().sqrt();
+		if norm > 0.0 {
+			data.iter_mut().for_each(|n| *n /= norm);
+		}
+		self.sites[k].data = data;
+	}
+
+	// Apply a gate to sites `k` and `k + 1`, truncating the bond between them
+	pub fn apply_pair(&mut self, k: usize, g: &Gate) {
+		self.move_center(k);
+		let (a, b) = (&self.sites[k], &self.sites[k + 1]);
+		let (left, d1, d2, right) = (a.left, a.dim, b.dim, b.right);
+		let get = |s: usize, t: usize| g.get(s).and_then(|v| v.get(t)).cloned().unwrap_or(Cf::zero());
+		// Two-site tensor theta[(l, s1), (s2, r)]
+		let mut theta = vec![vec![Cf::zero(); d2 * right]; left * d1];
+		for l in 0..left {
+			for s1 in 0..d1 {
+				for s2 in 0..d2 {
+					for r in 0..right {
+						let n = (0..a.right).fold(Cf::zero(), |sum, m| sum + a.get(l, s1, m) * b.get(m, s2, r));
+						if n.is_zero() {
+							continue
+						}
+						for t1 in 0..d1 {
+							for t2 in 0..d2 {
+								theta[l * d1 + t1][t2 * right + r] += get(s1 * d2 + s2, t1 * d2 + t2) * n;
+							}
+						}
+					}
+				}
+			}
+		}
+		let (u, s, vh) = svd(&theta);
+		let total = s.iter().map(|x| x * x).sum::<Real>();
+		let bond = ::std::cmp::min(s.len(), self.bond_cap);
+		let kept = s[..bond].iter().map(|x| x * x).sum::<Real>();
+		if total > 0.0 {
+			self.truncation += (total - kept) / total;
+		}
+		// A zero state stays zero rather than dividing by zero
+		let norm = if kept > 0.0 {kept.sqrt()} else {1.0};
+		let u = u.into_iter().map(|row| row[..bond].to_vec()).collect::<Matrix>();
+		let rest = vh[..bond].iter().zip(s.iter()).map(|(row, &x)| row.iter().map(|n| n * x / norm).collect()).collect::<Matrix>();
+		self.sites[k] = Site::from_matrix(left, d1, bond, &u);
+		self.sites[k + 1] = Site::from_matrix(bond, d2, right, &rest);
+		self.center = k + 1;
+	}
+
+	pub fn phase(mut self, p: Phase) -> Mps {
+		let c = phase_factor(p);
+		let center = self.center;
+		self.sites[center].data.iter_mut().for_each(|n| *n *= c);
+		self
+	}
+
+	// Product state of both registers
+	pub fn combine(mut self, mut m: Mps) -> Mps {
+		let last = self.sites.len() - 1;
+		self.move_center(last);
+		m.move_center(0);
+		self.sites.extend(m.sites);
+		self.bond_cap = ::std::cmp::max(self.bond_cap, m.bond_cap);
+		self.truncation += m.truncation;
+		self
+	}
+
+	// Sample an outcome for each site in order
+	pub fn measure<R: Rng>(&self, rng: &mut R) -> Vec<usize> {
+		let mut m = self.clone();
+		m.move_center(0);
+		let mut carry = vec![Cf::new(1.0, 0.0)];
+		let mut outcomes = vec![];
+		for site in m.sites.iter() {
+			// Amplitudes `v[s][r]` after contracting the previous outcomes
+			let v = (0..site.dim).map(|s| (0..site.right).map(|r| {
+				(0..site.left).fold(Cf::zero(), |sum, l| sum + carry[l] * site.get(l, s, r))
+			}).collect::<Vec<_>>()).collect::<Vec<_>>();
+			let probs = v.iter().map(|row| row.iter().map(|n| n.norm_sqr()).sum::<Real>()).collect::<Vec<_>>();
+			let s = sample_index(&probs, rng);
+			let norm = probs[s].sqrt();
+			carry = v[s].iter().map(|n| n / norm).collect();
+			outcomes.push(s);
+		}
+		outcomes
+	}
+
+	// Inner product ⟨self|m⟩ of states with the same dimensions, contracting one site at a time
+	pub fn overlap(&self, m: &Mps) -> Cf {
+		// `env[a][b]` for bond `a` of `self` and bond `b` of `m`
+		let mut env = vec![vec![Cf::new(1.0, 0.0)]];
+		for (x, y) in self.sites.iter().zip(m.sites.iter()) {
+			let mut next = vec![vec![Cf::zero(); y.right]; x.right];
+			for (a, row) in env.iter().enumerate() {
+				for (b, &e) in row.iter().enumerate() {
+					if e.is_zero() {
+						continue
+					}
+					for s in 0..x.dim {
+						for (r, out) in next.iter_mut().enumerate() {
+							let u = e * x.get(a, s, r).conj();
+							for (t, n) in out.iter_mut().enumerate() {
+								*n += u * y.get(b, s, t);
+							}
+						}
+					}
+				}
+			}
+			env = next;
+		}
+		env[0][0]
+	}
+
+	pub fn to_state(&self) -> State {
+		let mut cur = vec![Cf::new(1.0, 0.0)];
+		let mut bond = 1;
+		for site in self.sites.iter() {
+			let prefixes = cur.len() / bond;
+			let mut next = vec![Cf::zero(); prefixes * site.dim * site.right];
+			for p in 0..prefixes {
+				for m in 0..bond {
+					let x = cur[p * bond + m];
+					if x.is_zero() {
+						continue
+					}
+					for s in 0..site.dim {
+						for r in 0..site.right {
+							next[(p * site.dim + s) * site.right + r] += x * site.get(m, s, r);
+						}
+					}
+				}
+			}
+			cur = next;
+			bond = site.right;
+		}
+		cur
+	}
+}
+
+impl fmt::Display for Mps {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "mps[{}; bond {}]", self.dims().iter().map(|d| format!("{}", d)).collect::<Vec<_>>().join(", "), self.bond_dim())
+	}
+}
@@ -0,0 +1,128 @@
+use error::*;
+use engine::*;
+use stabilizer::*;
+
+use std::fmt;
+use num::Zero;
+
+// Weighted sum of Hermitian Pauli strings
+#[derive(Clone,Debug,PartialEq)]
+pub struct Observable {
+	pub terms: Vec<(Real, Pauli)>,
+}
+
+impl Observable {
+	// Parse a Pauli string such as "XZI" (qubit 0 first)
+	pub fn parse(s: &str) -> Ret<Observable> {
+		let n = s.chars().count();
+		let mut p = Pauli::identity(n);
+		for (i, c) in s.chars().enumerate() {
+			match c {
+				'I' => {},
+				'X' => p.x[i] = true,
+				'Z' => p.z[i] = true,
+				// Y = iXZ
+				'Y' => {
+					p.x[i] = true;
+					p.z[i] = true;
+					p.phase = (p.phase + 1) % 4;
+				},
+				_ => return err!("Invalid Pauli operator: {}", c),
+			}
+		}
+		Ok(Observable {terms: vec![(1.0, p)]})
+	}
+
+	// Empty Pauli string
+	pub fn identity() -> Observable {
+		Observable {terms: vec![(1.0, Pauli::identity(0))]}
+	}
+
+	pub fn qubits(&self) -> usize {
+		self.terms.first().map(|&(_, ref p)| p.len()).unwrap_or(0)
+	}
+
+	pub fn scale(self, w: Real) -> Observable {
+		Observable {terms: self.terms.into_iter().map(|(v, p)| (v * w, p)).collect()}
+	}
+
+	pub fn plus(mut self, o: Observable) -> Ret<Observable> {
+		if !self.terms.is_empty() && !o.terms.is_empty() && self.qubits() != o.qubits() {
+			return err!("Mismatched Pauli string lengths: {} and {}", self.qubits(), o.qubits())
+		}
+		self.terms.extend(o.terms);
+		Ok(self)
+	}
+
+	// Tensor product (Pauli strings of `o` follow those of `self`)
+	pub fn tensor(&self, o: &Observable) -> Observable {
+		Observable {terms: self.terms.iter().flat_map(|&(v, ref p)| o.terms.iter().map(move |&(w, ref q)| (v * w, Pauli {
+			x: p.x.iter().chain(q.x.iter()).cloned().collect(),
+			z: p.z.iter().chain(q.z.iter()).cloned().collect(),
+			phase: (p.phase + q.phase) % 4,
+		}))).collect()}
+	}
+
+	pub fn to_gate(&self) -> Gate {
+		let size = 1 << self.qubits();
+		let mut g = vec![vec![Cf::zero(); size]; size];
+		for &(w, ref p) in self.terms.iter() {
+			for (b, col) in g.iter_mut().enumerate() {
+				let (target, n) = p.apply_basis(b);
+				col[target] += n * w;
+			}
+		}
+		g
+	}
+
+	fn check_size(&self, size: usize) -> Ret {
+		if size != 1 << self.qubits() {
+			return err!("Observable on {} qubits applied to a {}-dimensional state", self.qubits(), size)
+		}
+		Ok(())
+	}
+
+	// ⟨ψ|O|ψ⟩
+	pub fn expect_state(&self, s: &State) -> Ret<Real> {
+		self.check_size(s.len())?;
+		Ok(self.terms.iter().map(|&(w, ref p)| {
+			w * s.iter().enumerate().fold(Cf::zero(), |sum, (b, x)| {
+				let (target, n) = p.apply_basis(b);
+				sum + s[target].conj() * n * x
+			}).re
+		}).sum())
+	}
+
+	// Tr(ρO)
+	pub fn expect_density(&self, rho: &Density) -> Ret<Real> {
+		self.check_size(rho.size())?;
+		Ok(self.terms.iter().map(|&(w, ref p)| {
+			w * (0..rho.size()).fold(Cf::zero(), |sum, b| {
+				let (target, n) = p.apply_basis(b);
+				sum + rho.0[b][target] * n
+			}).re
+		}).sum())
+	}
+
+	// Each Pauli string has expectation ±1 if it belongs to the stabilizer group (up to sign), otherwise 0
+	pub fn expect_tableau(&self, t: &Tableau) -> Ret<Real> {
+		if t.qubits != self.qubits() {
+			return err!("Observable on {} qubits applied to {} qubits", self.qubits(), t.qubits)
+		}
+		Ok(self.terms.iter().map(|&(w, ref p)| {
+			let mut negative = p.clone();
+			negative.phase = (negative.phase + 2) % 4;
+			if t.stabilizes(p) {w}
+			else if t.stabilizes(&negative) {-w}
+			else {0.0}
+		}).sum())
+	}
+}
+
+impl fmt::Display for Observable {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "pauli[{}]", self.terms.iter().map(|&(w, ref p)| {
+			if w == 1.0 {format!("{}", p)} else {format!("{} * {}", w, p)}
+		}).collect::<Vec<_>>().join(", "))
+	}
+}
@@ -0,0 +1,98 @@
+use error::*;
+use engine::Real;
+
+use std::cmp::Ordering;
+
+// Largest number of Nelder-Mead iterations per parameter
+const MAX_ITERATIONS: usize = 500;
+
+// Stop once the values across the simplex differ by at most this amount
+const TOLERANCE: Real = 1e-10;
+
+// Distance from the starting point to the other vertices of the initial simplex
+const INITIAL_STEP: Real = 0.25;
+
+// Step size of finite-difference gradients unless otherwise given, balancing truncation error against rounding error
+pub fn default_step() -> Real {
+	Real::EPSILON.cbrt()
+}
+
+// Gradient by the parameter-shift rule, which is exact when each parameter `θ` enters once as `@[θ] g` for a gate with `g(g(x)) == x`
+pub fn parameter_shift<F>(mut f: F, x: &[Real]) -> Ret<Vec<Real>>
+where F: FnMut(&[Real]) -> Ret<Real> {
+	(0..x.len()).map(|i| {
+		let mut shifted = x.to_vec();
+		shifted[i] = x[i] + 0.5;
+		let plus = f(&shifted)?;
+		shifted[i] = x[i] - 0.5;
+		let minus = f(&shifted)?;
+		Ok(::std::f64::consts::FRAC_PI_2 * (plus - minus))
+	}).collect()
+}
+
+// Gradient by central finite differences
+pub fn finite_difference<F>(mut f: F, x: &[Real], step: Real) -> Ret<Vec<Real>>
+where F: FnMut(&[Real]) -> Ret<Real> {
+	if step <= 0.0 {
+		return err!("Invalid step size: {}", step)
+	}
+	(0..x.len()).map(|i| {
+		let mut shifted = x.to_vec();
+		shifted[i] = x[i] + step;
+		let plus = f(&shifted)?;
+		shifted[i] = x[i] - step;
+		let minus = f(&shifted)?;
+		Ok((plus - minus) / (2.0 * step))
+	}).collect()
+}
+
+// Minimize a function with the Nelder-Mead simplex method, returning the best point and its value
+pub fn nelder_mead<F>(mut f: F, x0: &[Real]) -> Ret<(Vec<Real>, Real)>
+where F: FnMut(&[Real]) -> Ret<Real> {
+	let n = x0.len();
+	let mut simplex = vec![(x0.to_vec(), f(x0)?)];
+	for i in 0..n {
+		let mut x = x0.to_vec();
+		x[i] += INITIAL_STEP;
+		let value = f(&x)?;
+		simplex.push((x, value));
+	}
+	let order = |a: &(Vec<Real>, Real), b: &(Vec<Real>, Real)| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal);
+	for _ in 0..MAX_ITERATIONS * n {
+		simplex.sort_by(&order);
+		if simplex[n].1 - simplex[0].1 <= TOLERANCE {
+			break
+		}
+		let centroid = (0..n).map(|j| simplex[..n].iter().map(|p| p.0[j]).sum::<Real>() / n as Real).collect::<Vec<_>>();
+		let worst = simplex[n].clone();
+		// Point along the line from the centroid to the worst vertex
+		let towards = |t: Real| centroid.iter().zip(worst.0.iter()).map(|(c, w)| c + t * (w - c)).collect::<Vec<_>>();
+		let reflected = towards(-1.0);
+		let fr = f(&reflected)?;
+		if fr < simplex[0].1 {
+			let expanded = towards(-2.0);
+			let fe = f(&expanded)?;
+			simplex[n] = if fe < fr {(expanded, fe)} else {(reflected, fr)};
+		}
+		else if fr < simplex[n - 1].1 {
+			simplex[n] = (reflected, fr);
+		}
+		else {
+			let contracted = if fr < worst.1 {towards(-0.5)} else {towards(0.5)};
+			let fc = f(&contracted)?;
+			if fc < fr.min(worst.1) {
+				simplex[n] = (contracted, fc);
+			}
+			else {
+				// Shrink towards the best vertex
+				let best = simplex[0].0.clone();
+				for p in simplex[1..].iter_mut() {
+					p.0 = best.iter().zip(p.0.iter()).map(|(b, x)| b + 0.5 * (x - b)).collect();
+					p.1 = f(&p.0)?;
+				}
+			}
+		}
+	}
+	simplex.sort_by(&order);
+	Ok(simplex.swap_remove(0))
+}
@@ -1,7 +1,7 @@
 use error::*;
 use resource;
 use ast::*;
-use engine::Phase;
+use engine::{Phase, Real};
 
 use std::rc::Rc;
 use regex::Regex;
@@ -13,7 +13,7 @@ fn is_ident_char(c: u8) -> bool {
 }
 
 fn is_opr_char(c: u8) -> bool {
-	b"~!@#$%^&*/?|-+<>.".contains(&c)
+	b"~!@#$%^&*/?|-+<>.=".contains(&c)
 }
 
 fn with_anno(exp: Exp, anno: Option<Pat>) -> Exp {
@@ -41,6 +41,32 @@ named!(int_literal<isize>, do_parse!(
 	(nat as isize * sig.unwrap_or(1))
 ));
 
+// Digits with an optional fraction and exponent
+named!(decimal, recognize!(tuple!(
+	take_while1!(nom::is_digit),
+	opt!(complete!(preceded!(tag!("."), take_while1!(nom::is_digit)))),
+	opt!(complete!(tuple!(one_of!("eE"), opt!(tag!("-")), take_while1!(nom::is_digit))))
+)));
+
+named!(real_literal<Real>, ws!(map_res!(
+	map_res!(verify!(decimal, |s: &[u8]| s.iter().any(|&c| c == b'.' || c == b'e' || c == b'E')), ::std::str::from_utf8),
+	|s: &str| s.parse()
+)));
+
+named!(signed_real<Real>, do_parse!(
+	sig: opt!(value!(-1.0, ws!(tag!("-")))) >>
+	n: alt!(real_literal | index_literal => {|n| n as Real}) >>
+	(n * sig.unwrap_or(1.0))
+));
+
+named!(ident_chars, take_while1!(is_ident_char));
+
+// Imaginary literal such as `0.5i` (distinct from a repeated identifier starting with `i`)
+named!(imag_literal<Real>, ws!(map_res!(
+	map_res!(terminated!(decimal, pair!(tag!("i"), not!(complete!(ident_chars)))), ::std::str::from_utf8),
+	|s: &str| s.parse()
+)));
+
 named!(string_literal<String>, ws!(delimited!(
 	tag!("\""),
 	fold_many0!(
@@ -58,18 +84,38 @@ named!(index_literal<usize>,
 	alt!(hex_literal | bin_literal | dec_literal)
 );
 
+named!(number_exp<Exp>, alt!(
+	imag_literal => {|n| Exp::Complex(Phase::new(0_f64, n))} |
+	real_literal => {Exp::Real} |
+	index_literal => {Exp::Index}
+));
+
 named!(literal_exp<Exp>, alt!(
-	index_literal => {Exp::Index} |
+	number_exp |
 	string_literal => {Exp::String}
 ));
 
+// State literal such as `|F⟩`, optionally scaled by an amplitude (e.g. `0.6|F⟩`)
+// The closing `⟩` keeps kets distinct from the `|` and `>` operators
+named!(ket_exp<Exp>, do_parse!(
+	amp: opt!(complete!(number_exp)) >>
+	complete!(ws!(tag!("|"))) >>
+	exp: target_exp >>
+	complete!(ws!(tag!("⟩"))) >>
+	(match amp {
+		None => Exp::State(Rc::new(exp)),
+		Some(amp) => Exp::Scale(Rc::new(amp), Rc::new(Exp::State(Rc::new(exp)))),
+	})
+));
+
 named!(name_ident<String>, ws!(map!(
 	map_res!(take_while1!(is_ident_char), ::std::str::from_utf8),
 	|s| s.to_string()
 )));
 
+// Operator name (only `==` may start with `=`, which otherwise separates declarations and cases)
 named!(opr_ident<String>, ws!(map!(
-	map_res!(take_while1!(is_opr_char), ::std::str::from_utf8),
+	map_res!(alt!(complete!(tag!("==")) | recognize!(pair!(verify!(take!(1), |c: &[u8]| c[0] != b'=' && is_opr_char(c[0])), take_while!(is_opr_char)))), ::std::str::from_utf8),
 	|s| s.to_string()
 )));
 
@@ -91,8 +137,14 @@ named!(tuple_exp<Exp>, map!(
 	|vec| if vec.len() == 1 && match vec[..] {[Exp::Tuple(_)] => false, _ => true} {vec[0].clone()} else {Exp::Tuple(vec)}
 ));
 
+// Binary operator, as opposed to a prefix operator which is written next to its argument
+named!(spaced_opr, terminated!(take_while1!(is_opr_char), nom::multispace));
+
 named!(repeat_exp<Exp>,	do_parse!(
+	not!(complete!(alt!(imag_literal | real_literal))) >>
 	n: index_literal >>
+	// `1 + 2` is an arithmetic expression rather than a repeated `+2` (while `2 ~T` repeats `~T`)
+	not!(complete!(spaced_opr)) >>
 	exp: target_exp >>
 	(Exp::Repeat(n, Rc::new(exp)))
 ));
@@ -182,25 +234,31 @@ named!(lambda_exp<Exp>, do_parse!(
 ));
 
 named!(phase_exp<Exp>, do_parse!(
-	phase: preceded!(ws!(tag!("@")), delimited!(
-		ws!(tag!("[")),
-		tuple!(phase, opt!(map!(preceded!(ws!(tag!(",")), phase), |p| p * Phase::i()))),
-		ws!(tag!("]"))
+	phase: preceded!(ws!(tag!("@")), alt!(
+		delimited!(
+			ws!(tag!("[")),
+			tuple!(phase, opt!(map!(preceded!(ws!(tag!(",")), phase), |p| p * Phase::i()))),
+			ws!(tag!("]"))
+		) => {|(re, im): (Phase, Option<Phase>)| match im {
+			Some(im) => Exp::Complex(re + im),
+			None => Exp::Real(re.re),
+		}} |
+		delimited!(ws!(tag!("[")), exp, ws!(tag!("]")))
 	)) >>
 	exp: target_exp >>
-	(Exp::Phase(phase.0 + phase.1.unwrap_or(::num::Zero::zero()), Rc::new(exp)))
+	(Exp::Phase(Rc::new(phase), Rc::new(exp)))
 ));
 
 named!(phase<Phase>, do_parse!(
 	num: int_literal >>
 	size: alt!(
-		preceded!(ws!(tag!("/")), map!(int_literal, |n| n as f32)) |
-		value!(100_f32, ws!(tag!("%"))) |
-		value!(180_f32, ws!(tag!("d"))) |
-		value!(::std::f32::consts::PI, ws!(tag!("r"))) |
-		value!(1_f32)
+		preceded!(ws!(tag!("/")), map!(int_literal, |n| n as Real)) |
+		value!(100_f64, ws!(tag!("%"))) |
+		value!(180_f64, ws!(tag!("d"))) |
+		value!(::std::f64::consts::PI, ws!(tag!("r"))) |
+		value!(1_f64)
 	) >>
-	(Phase::new(num as f32 / size, 0_f32))
+	(Phase::new(num as Real / size, 0_f64))
 ));
 
 named!(path_exp<Exp>,
@@ -222,11 +280,21 @@ named!(anno_exp<Exp>, do_parse!(
 named!(arg_exp<Exp>, alt!(
 	preceded!(ws!(tag!("...")), alt!(repeat_exp | exp)) => {|exp| Exp::Expand(Rc::new(exp))} |
 	repeat_exp |
-	exp
+	pair_exp
 	// exp |
 	// map!(opr_ident, Exp::Var)
 ));
 
+// Argument of the form `a => b`, passed as the tuple `(a, b)`
+named!(pair_exp<Exp>, do_parse!(
+	key: exp >>
+	result: opt!(complete!(preceded!(ws!(tag!("=>")), exp))) >>
+	(match result {
+		None => key,
+		Some(result) => Exp::Tuple(vec![key, result]),
+	})
+));
+
 //named!(loop_exp<Exp>, preceded!(ws!(tag!("for")), do_parse!(
 //	pat_val: alt!(tuple!(pat, preceded!(ws!(tag!("in")), exp)) | tuple!(value!(Pat::Any), exp)) >>
 //	filter: opt!(preceded!(ws!(tag!("where")), exp)) >>
@@ -241,17 +309,47 @@ named!(prefix_opr_exp<Exp>, do_parse!(
 ));
 
 named!(target_exp<Exp>,
-	alt!(phase_exp | prefix_opr_exp | cond_exp | anno_exp/* | loop_exp*/ | lambda_exp)
+	alt!(ket_exp | phase_exp | prefix_opr_exp | cond_exp | anno_exp/* | loop_exp*/ | lambda_exp)
 );
 
 named!(exp<Exp>, do_parse!(
 	exp: target_exp >>
 	infixes: many0!(pair!(opr_ident, target_exp)) >>
-	(infixes.into_iter().fold(exp, |a, (opr, b)| Exp::Invoke(
-		Rc::new(Exp::Var(opr)),
-		Rc::new(Exp::Tuple(vec![a, b])),
-	)))
-));
+	(fold_infixes(exp, infixes))
+));
+
+// Binding strength of infix operators (all operators are left-associative)
+fn precedence(opr: &str) -> usize {
+	match opr {
+		"==" | "!=" | "~=" | "<" | ">" | "<=" | ">=" => 0,
+		"|" => 1,
+		"&" => 2,
+		"*" | "/" | "%" => 5,
+		"+" | "-" => 4,
+		_ => 3,
+	}
+}
+
+fn fold_infixes(exp: Exp, infixes: Vec<(String, Exp)>) -> Exp {
+	fn reduce(operands: &mut Vec<Exp>, opr: String) {
+		let b = operands.pop().unwrap();
+		let a = operands.pop().unwrap();
+		operands.push(Exp::Invoke(Rc::new(Exp::Var(opr)), Rc::new(Exp::Tuple(vec![a, b]))));
+	}
+	let mut operands = vec![exp];
+	let mut oprs: Vec<String> = vec![];
+	for (opr, exp) in infixes {
+		while oprs.last().map(|top| precedence(top) >= precedence(&opr)).unwrap_or(false) {
+			reduce(&mut operands, oprs.pop().unwrap());
+		}
+		oprs.push(opr);
+		operands.push(exp);
+	}
+	while let Some(opr) = oprs.pop() {
+		reduce(&mut operands, opr);
+	}
+	operands.pop().unwrap()
+}
 
 named!(let_decl<Decl>, do_parse!(
 	ws!(tag!("let")) >>
@@ -314,11 +412,27 @@ named!(fn_part<Exp>,
 
 named!(assert_decl<Decl>, do_parse!(
 	ws!(tag!("assert")) >>
-	expect: exp >>
-	ws!(tag!("==")) >>
-	result: exp >>
-	(Decl::Assert(expect, result))
-));
+	comparison: map_opt!(exp, split_comparison) >>
+	tolerance: opt!(complete!(preceded!(ws!(tag!("within")), signed_real))) >>
+	(Decl::Assert(comparison.0, comparison.1, comparison.2, tolerance))
+));
+
+// Operands of an assertion such as `a == b` or `a ~= b`
+fn split_comparison(exp: Exp) -> Option<(Exp, Exp, Compare)> {
+	if let Exp::Invoke(f, arg) = exp {
+		if let (&Exp::Var(ref opr), &Exp::Tuple(ref args)) = (&*f, &*arg) {
+			let compare = match opr.as_str() {
+				"==" => Compare::Equal,
+				"~=" => Compare::Similar,
+				_ => return None,
+			};
+			if args.len() == 2 {
+				return Some((args[0].clone(), args[1].clone(), compare))
+			}
+		}
+	}
+	None
+}
 
 named!(print_decl<Decl>, do_parse!(
 	ws!(tag!("print")) >>
@@ -0,0 +1,381 @@
+use error::*;
+use engine::*;
+
+use rand::Rng;
+use num::{Zero, One};
+use num::complex::Complex;
+use std::collections::{BTreeMap, HashMap};
+
+// State vector of `size` entries, storing only the nonzero amplitudes
+#[derive(Clone,Debug,PartialEq)]
+pub struct SparseState {
+	pub size: usize,
+	pub entries: BTreeMap<usize, Cf>,
+}
+
+// Gate stored by input (`cols[i]` is the output state for input `i`)
+#[derive(Clone,Debug,PartialEq)]
+pub struct SparseGate {
+	pub width: usize,
+	pub cols: Vec<SparseState>,
+}
+
+impl SparseState {
+	pub fn new(size: usize) -> SparseState {
+		SparseState {size, entries: BTreeMap::new()}
+	}
+
+	// Basis state `i` of a `size`-dimensional space
+	pub fn basis(size: usize, i: usize) -> SparseState {
+		let mut s = SparseState::new(size);
+		s.add(i, Complex::one());
+		s
+	}
+
+	pub fn from_dense(s: &State) -> SparseState {
+		SparseState {
+			size: s.len(),
+			entries: s.iter().cloned().enumerate().filter(|&(_, n)| !n.is_zero()).collect(),
+		}
+	}
+
+	pub fn to_dense(&self) -> State {
+		let mut s = vec![Complex::zero(); self.size];
+		for (&i, &n) in self.entries.iter() {
+			s[i] = n;
+		}
+		s
+	}
+
+	pub fn nnz(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn get(&self, i: usize) -> Cf {
+		self.entries.get(&i).cloned().unwrap_or(Complex::zero())
+	}
+
+	// Fraction of nonzero entries
+	pub fn density(&self) -> f64 {
+		self.nnz() as f64 / ::std::cmp::max(1, self.size) as f64
+	}
+
+	// Remove entries which cancelled out to exactly zero
+	pub fn prune(mut self) -> SparseState {
+		self.entries.retain(|_, n| !n.is_zero());
+		self
+	}
+
+	// Accumulate `n` at index `i`
+	pub fn add(&mut self, i: usize, n: Cf) {
+		if n.is_zero() {
+			return
+		}
+		*self.entries.entry(i).or_insert(Complex::zero()) += n;
+		self.size = ::std::cmp::max(self.size, i + 1);
+	}
+
+	fn map<F>(self, f: F) -> SparseState
+	where F: Fn(Cf) -> Cf {
+		SparseState {
+			size: self.size,
+			entries: self.entries.into_iter().map(|(i, n)| (i, f(n))).collect(),
+		}
+	}
+}
+
+impl Stateful for SparseState {
+	fn pad(mut self, n: usize) -> SparseState {
+		self.size = ::std::cmp::max(self.size, n);
+		self
+	}
+
+	fn sup(self, s: SparseState) -> SparseState {
+		create_sparse_sup(vec![self, s])
+	}
+
+	fn normalized(self) -> SparseState {
+		let div = self.prob_sum().sqrt();
+		self.map(|n| n / div)
+	}
+
+	fn phase(self, p: Cf) -> SparseState {
+		let c = phase_factor(p);
+		self.map(|n| n * c)
+	}
+
+	fn phase_flip(self) -> SparseState {
+		self.map(|n| -n)
+	}
+
+	fn prob_sum(&self) -> Real {
+		self.entries.values().fold(0.0, |a, b| a + b.norm_sqr())
+	}
+
+	fn probs(&self) -> Vec<Real> {
+		let mut probs = vec![0.0; self.size];
+		for (&i, n) in self.entries.iter() {
+			probs[i] = n.norm_sqr();
+		}
+		probs
+	}
+
+	// Sample only among the stored amplitudes
+	fn measure<R: Rng>(self, rng: &mut R) -> usize {
+		let (indices, probs): (Vec<usize>, Vec<Real>) = self.entries.iter().map(|(&i, n)| (i, n.norm_sqr())).unzip();
+		indices.get(sample_index(&probs, rng)).cloned().unwrap_or(0)
+	}
+}
+
+// Create a superposition of the given sparse states (see `engine::create_sup`)
+pub fn create_sparse_sup(states: Vec<SparseState>) -> SparseState {
+	let div = (states.len() as f64).sqrt();
+	let mut sum = SparseState::new(0);
+	for s in states {
+		sum = sum.pad(s.size);
+		for (i, n) in s.entries {
+			sum.add(i, n);
+		}
+	}
+	sum.map(|n| n / div)
+}
+
+// Inner product ⟨a|b⟩ of sparse states
+pub fn sparse_overlap(a: &SparseState, b: &SparseState) -> Cf {
+	a.entries.iter().fold(Complex::zero(), |sum, (&i, x)| sum + x.conj() * b.get(i))
+}
+
+impl Combine for SparseState {
+	fn combine(self, s: SparseState) -> SparseState {
+		let mut state = SparseState::new(self.size * s.size);
+		for (i, x) in self.entries {
+			for (&j, &y) in s.entries.iter() {
+				state.add(i * s.size + j, x * y);
+			}
+		}
+		state
+	}
+}
+
+impl SparseGate {
+	pub fn from_dense(g: &Gate) -> SparseGate {
+		SparseGate {
+			width: g.width(),
+			cols: g.iter().map(|s| SparseState::from_dense(s).pad(g.width())).collect(),
+		}
+	}
+
+	pub fn to_dense(&self) -> Gate {
+		self.cols.iter().map(|s| s.to_dense().pad(self.width)).collect()
+	}
+
+	pub fn len(&self) -> usize {
+		self.cols.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.cols.is_empty()
+	}
+
+	pub fn nnz(&self) -> usize {
+		self.cols.iter().map(|s| s.nnz()).sum()
+	}
+
+	fn map<F>(self, f: F) -> SparseGate
+	where F: Fn(Cf) -> Cf {
+		SparseGate {
+			width: self.width,
+			cols: self.cols.into_iter().map(|s| s.map(&f)).collect(),
+		}
+	}
+
+	fn is_diagonal(&self) -> bool {
+		self.cols.iter().enumerate().all(|(i, s)| s.entries.iter().all(|(&j, n)| i == j || n.is_zero()))
+	}
+}
+
+// Apply a sparse gate to axis `k` of a state (see `engine::apply_axis`)
+pub fn apply_axis_sparse(s: &mut State, dims: &[usize], k: usize, g: &SparseGate) {
+	let d = dims[k];
+	let inner: usize = dims[k + 1..].iter().product();
+	let outer = s.len() / (d * inner);
+	let mut fiber = vec![Complex::zero(); d];
+	for o in 0..outer {
+		for r in 0..inner {
+			let base = o * d * inner + r;
+			for (m, x) in fiber.iter_mut().enumerate() {
+				*x = s[base + m * inner];
+				s[base + m * inner] = Complex::zero();
+			}
+			for (x, col) in fiber.iter().zip(g.cols.iter()) {
+				if x.is_zero() {
+					continue
+				}
+				for (&j, &y) in col.entries.iter() {
+					s[base + j * inner] += x * y;
+				}
+			}
+		}
+	}
+}
+
+// Apply a gate to axis `k` of a sparse state, given the nonzero outputs for each input of the gate
+// (see `engine::apply_axis`)
+pub fn sparse_apply_axis<F>(s: &SparseState, dims: &[usize], k: usize, column: F) -> SparseState
+where F: Fn(usize) -> Vec<(usize, Cf)> {
+	let d = dims[k];
+	let inner: usize = dims[k + 1..].iter().product();
+	let mut out = SparseState::new(s.size);
+	for (&i, &x) in s.entries.iter() {
+		let m = (i / inner) % d;
+		let base = i - m * inner;
+		for (j, y) in column(m) {
+			out.add(base + j * inner, x * y);
+		}
+	}
+	out
+}
+
+// Fraction of nonzero entries in a dense gate
+pub fn gate_density(g: &Gate) -> f64 {
+	let size = g.len() * g.width();
+	let nnz = g.iter().map(|s| s.iter().filter(|n| !n.is_zero()).count()).sum::<usize>();
+	if size == 0 {1.0} else {nnz as f64 / size as f64}
+}
+
+impl Extract<SparseGate> for SparseState {
+	fn extract(self, g: SparseGate) -> SparseState {
+		let mut state = SparseState::new(g.width);
+		for (i, x) in self.entries {
+			if let Some(col) = g.cols.get(i) {
+				for (&j, &y) in col.entries.iter() {
+					state.add(j, x * y);
+				}
+			}
+		}
+		state
+	}
+}
+
+impl Extract<SparseGate> for State {
+	fn extract(self, g: SparseGate) -> State {
+		let mut state = vec![Complex::zero(); g.width];
+		for (x, col) in self.into_iter().zip(g.cols.iter()) {
+			if x.is_zero() {
+				continue
+			}
+			for (&j, &y) in col.entries.iter() {
+				state[j] += x * y;
+			}
+		}
+		state
+	}
+}
+
+impl Extract<SparseGate> for SparseGate {
+	fn extract(self, g: SparseGate) -> SparseGate {
+		SparseGate {
+			width: g.width,
+			cols: self.cols.into_iter().map(|s| s.extract(g.clone())).collect(),
+		}
+	}
+}
+
+impl Extract<SparseGate> for Density {
+	// ρ -> UρU†
+	fn extract(self, g: SparseGate) -> Density {
+		let width = g.width;
+		let mut rho = vec![vec![Complex::zero(); width]; width];
+		for (i, row) in self.0.iter().enumerate() {
+			for (j, &n) in row.iter().enumerate() {
+				if n.is_zero() {
+					continue
+				}
+				if let (Some(a), Some(b)) = (g.cols.get(i), g.cols.get(j)) {
+					for (&x, &u) in a.entries.iter() {
+						for (&y, &v) in b.entries.iter() {
+							rho[x][y] += u * n * v.conj();
+						}
+					}
+				}
+			}
+		}
+		Density(rho)
+	}
+}
+
+impl Combine for SparseGate {
+	fn combine(self, g: SparseGate) -> SparseGate {
+		let mut cols = vec![];
+		for x in self.cols {
+			for y in g.cols.iter() {
+				cols.push(x.clone().combine(y.clone()));
+			}
+		}
+		SparseGate {width: self.width * g.width, cols}
+	}
+}
+
+impl MatrixLike for SparseGate {
+	fn width(&self) -> usize {self.width}
+
+	fn unitarity(&self) -> Unitarity {
+		// Inner products between inputs which share an output
+		let mut outputs: HashMap<usize, Vec<(usize, Cf)>> = HashMap::new();
+		for (i, s) in self.cols.iter().enumerate() {
+			for (&j, &n) in s.entries.iter() {
+				outputs.entry(j).or_default().push((i, n));
+			}
+		}
+		let mut inner: HashMap<(usize, usize), Cf> = HashMap::new();
+		for entries in outputs.values() {
+			for &(a, x) in entries {
+				for &(b, y) in entries {
+					*inner.entry((a, b)).or_insert(Complex::zero()) += x.conj() * y;
+				}
+			}
+		}
+		let normalized = (0..self.len()).all(|i| (inner.get(&(i, i)).cloned().unwrap_or(Complex::zero()) - Complex::one()).norm() <= EPSILON);
+		let orthogonal = inner.iter().all(|(&(a, b), n)| a == b || n.norm() <= EPSILON);
+		if !normalized || !orthogonal {Unitarity::NonUnitary}
+		else if self.width > self.len() {Unitarity::Isometry}
+		else {Unitarity::Unitary}
+	}
+
+	fn inverse(self) -> SparseGate {
+		let mut cols = vec![SparseState::new(self.len()); self.width];
+		for (i, s) in self.cols.iter().enumerate() {
+			for (&j, &n) in s.entries.iter() {
+				cols[j].add(i, n.conj());
+			}
+		}
+		SparseGate {width: self.len(), cols}
+	}
+
+	fn negate(self) -> SparseGate {
+		self.map(|n| -n)
+	}
+
+	fn power(self, p: Cf) -> Ret<SparseGate> {
+		if p.is_one() {
+			return Ok(self)
+		}
+		if self.is_diagonal() {
+			let cols = self.cols.into_iter().enumerate().map(|(i, s)| {
+				let mut col = SparseState::new(s.size);
+				col.add(i, principal_power(s.get(i), p)?);
+				Ok(col)
+			}).collect::<Ret<_>>()?;
+			return Ok(SparseGate {width: self.width, cols})
+		}
+		Ok(SparseGate::from_dense(&self.to_dense().power(p)?))
+	}
+
+	fn expm(self) -> Ret<SparseGate> {
+		Ok(SparseGate::from_dense(&self.to_dense().expm()?))
+	}
+
+	fn logm(self) -> Ret<SparseGate> {
+		Ok(SparseGate::from_dense(&self.to_dense().logm()?))
+	}
+}
@@ -0,0 +1,272 @@
+use engine::*;
+use linalg::{Matrix, mul, adjoint};
+
+use std::fmt;
+use rand::Rng;
+use num::{Zero, One};
+
+// When to simulate Clifford gates on a stabilizer tableau rather than a state vector
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum StabilizerMode {
+	// Only for registers of at least `STABILIZER_MIN_QUBITS` qubits
+	Auto,
+	Always,
+	Never,
+}
+
+pub const STABILIZER_MIN_QUBITS: usize = 20;
+
+// Largest stabilizer state which may be converted back to a state vector
+pub const MAX_DENSE_QUBITS: usize = 26;
+
+// Largest gate (in qubits) checked for the Clifford property
+const MAX_CLIFFORD_QUBITS: usize = 2;
+
+// Pauli operator i^phase * X^x[0] Z^z[0] ⊗ X^x[1] Z^z[1] ⊗ ...
+#[derive(Clone,Debug,PartialEq)]
+pub struct Pauli {
+	pub x: Vec<bool>,
+	pub z: Vec<bool>,
+	pub phase: u8,
+}
+
+impl Pauli {
+	pub fn identity(n: usize) -> Pauli {
+		Pauli {x: vec![false; n], z: vec![false; n], phase: 0}
+	}
+
+	pub fn len(&self) -> usize {
+		self.x.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.x.is_empty()
+	}
+
+	pub fn mul(&self, p: &Pauli) -> Pauli {
+		// Moving each Z past the following X contributes a factor of -1
+		let swaps = self.z.iter().zip(p.x.iter()).filter(|&(&z, &x)| z && x).count();
+		Pauli {
+			x: self.x.iter().zip(p.x.iter()).map(|(a, b)| a ^ b).collect(),
+			z: self.z.iter().zip(p.z.iter()).map(|(a, b)| a ^ b).collect(),
+			phase: ((self.phase as usize + p.phase as usize + 2 * swaps) % 4) as u8,
+		}
+	}
+
+	pub fn commutes(&self, p: &Pauli) -> bool {
+		(0..self.len()).filter(|&i| (self.x[i] && p.z[i]) != (self.z[i] && p.x[i])).count() % 2 == 0
+	}
+
+	// Sign of the Hermitian operator (each XZ counts as -iY)
+	fn is_negative(&self) -> bool {
+		let ys = self.x.iter().zip(self.z.iter()).filter(|&(&x, &z)| x && z).count();
+		(self.phase as usize + 4 - ys % 4) % 4 == 2
+	}
+
+	// Apply to the basis state `b` (qubit 0 is the most significant bit)
+	pub fn apply_basis(&self, b: usize) -> (usize, Cf) {
+		let n = self.len();
+		let bit = |i: usize| 1 << (n - 1 - i);
+		let flips = (0..n).filter(|&i| self.z[i] && b & bit(i) != 0).count();
+		let target = (0..n).filter(|&i| self.x[i]).fold(b, |b, i| b ^ bit(i));
+		let units = [Cf::new(1.0, 0.0), Cf::new(0.0, 1.0), Cf::new(-1.0, 0.0), Cf::new(0.0, -1.0)];
+		(target, units[(self.phase as usize + 2 * flips) % 4])
+	}
+
+	pub fn matrix(&self) -> Matrix {
+		let size = 1 << self.len();
+		let mut m = vec![vec![Cf::zero(); size]; size];
+		for (b, (target, n)) in (0..size).map(|b| self.apply_basis(b)).enumerate() {
+			m[target][b] = n;
+		}
+		m
+	}
+}
+
+impl fmt::Display for Pauli {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", if self.is_negative() {'-'} else {'+'})?;
+		for (&x, &z) in self.x.iter().zip(self.z.iter()) {
+			write!(f, "{}", match (x, z) {
+				(false, false) => 'I',
+				(true, false) => 'X',
+				(false, true) => 'Z',
+				(true, true) => 'Y',
+			})?;
+		}
+		Ok(())
+	}
+}
+
+// Clifford gate given by its action on the Pauli operators (images of X and Z for each qubit)
+#[derive(Clone,Debug,PartialEq)]
+pub struct Clifford {
+	pub qubits: usize,
+	pub x_images: Vec<Pauli>,
+	pub z_images: Vec<Pauli>,
+}
+
+// Find the Pauli conjugation performed by a small unitary gate, if it is Clifford
+pub fn as_clifford(g: &Gate) -> Option<Clifford> {
+	let qubits = qubit_count(g.len())?;
+	if qubits > MAX_CLIFFORD_QUBITS || g.width() > g.len() || !g.is_unitary() {
+		return None
+	}
+	let size = g.len();
+	// `gate[i]` is the output for input `i`, so the gate is the transposed matrix
+	let u: Matrix = (0..size).map(|j| g.iter().map(|s| s.get(j).cloned().unwrap_or(Cf::zero())).collect()).collect();
+	let image = |p: Pauli| {
+		let m = mul(&mul(&u, &p.matrix()), &adjoint(&u));
+		all_paulis(qubits).into_iter().find(|q| {
+			let qm = q.matrix();
+			m.iter().zip(qm.iter()).all(|(a, b)| a.iter().zip(b.iter()).all(|(a, b)| (a - b).norm() < EPSILON))
+		})
+	};
+	let single = |i: usize, z: bool| {
+		let mut p = Pauli::identity(qubits);
+		if z {p.z[i] = true} else {p.x[i] = true}
+		p
+	};
+	Some(Clifford {
+		qubits,
+		x_images: (0..qubits).map(|i| image(single(i, false))).collect::<Option<_>>()?,
+		z_images: (0..qubits).map(|i| image(single(i, true))).collect::<Option<_>>()?,
+	})
+}
+
+fn all_paulis(n: usize) -> Vec<Pauli> {
+	(0..1 << (2 * n)).flat_map(|bits: usize| (0..4).map(move |phase| Pauli {
+		x: (0..n).map(|i| bits & (1 << i) != 0).collect(),
+		z: (0..n).map(|i| bits & (1 << (n + i)) != 0).collect(),
+		phase,
+	})).collect()
+}
+
+pub fn qubit_count(size: usize) -> Option<usize> {
+	if size.is_power_of_two() {Some(size.trailing_zeros() as usize)} else {None}
+}
+
+// Stabilizer state (tracked up to global phase) as a tableau of destabilizer and stabilizer generators
+#[derive(Clone,Debug,PartialEq)]
+pub struct Tableau {
+	pub qubits: usize,
+	// Destabilizers followed by stabilizers
+	pub rows: Vec<Pauli>,
+}
+
+impl Tableau {
+	pub fn from_bits(bits: &[bool]) -> Tableau {
+		let n = bits.len();
+		let destabilizers = (0..n).map(|i| {
+			let mut p = Pauli::identity(n);
+			p.x[i] = true;
+			p
+		});
+		let stabilizers = bits.iter().enumerate().map(|(i, &b)| {
+			let mut p = Pauli::identity(n);
+			p.z[i] = true;
+			p.phase = if b {2} else {0};
+			p
+		});
+		Tableau {qubits: n, rows: destabilizers.chain(stabilizers).collect()}
+	}
+
+	pub fn stabilizers(&self) -> &[Pauli] {
+		&self.rows[self.qubits..]
+	}
+
+	// Apply a Clifford gate to the qubits starting at `offset`
+	pub fn apply(&mut self, c: &Clifford, offset: usize) {
+		for row in self.rows.iter_mut() {
+			let mut local = Pauli::identity(c.qubits);
+			for j in 0..c.qubits {
+				if row.x[offset + j] {
+					local = local.mul(&c.x_images[j]);
+				}
+				if row.z[offset + j] {
+					local = local.mul(&c.z_images[j]);
+				}
+			}
+			for j in 0..c.qubits {
+				row.x[offset + j] = local.x[j];
+				row.z[offset + j] = local.z[j];
+			}
+			row.phase = (row.phase + local.phase) % 4;
+		}
+	}
+
+	pub fn measure<R: Rng>(&mut self, rng: &mut R) -> Vec<bool> {
+		(0..self.qubits).map(|a| self.measure_qubit(a, || rng.gen())).collect()
+	}
+
+	// Measure qubit `a`, using `choose` to pick the outcome when it is random
+	pub fn measure_qubit<F: FnMut() -> bool>(&mut self, a: usize, mut choose: F) -> bool {
+		let n = self.qubits;
+		match (n..2 * n).find(|&p| self.rows[p].x[a]) {
+			Some(p) => {
+				let pivot = self.rows[p].clone();
+				for i in 0..2 * n {
+					if i != p && self.rows[i].x[a] {
+						self.rows[i] = self.rows[i].mul(&pivot);
+					}
+				}
+				let outcome = choose();
+				let mut z = Pauli::identity(n);
+				z.z[a] = true;
+				z.phase = if outcome {2} else {0};
+				self.rows[p - n] = pivot;
+				self.rows[p] = z;
+				outcome
+			},
+			None => {
+				let product = (0..n).filter(|&i| self.rows[i].x[a])
+					.fold(Pauli::identity(n), |p, i| p.mul(&self.rows[i + n]));
+				product.phase == 2
+			},
+		}
+	}
+
+	// Check whether a Pauli operator belongs to the stabilizer group
+	pub fn stabilizes(&self, p: &Pauli) -> bool {
+		let n = self.qubits;
+		if !self.stabilizers().iter().all(|s| s.commutes(p)) {
+			return false
+		}
+		let product = (0..n).filter(|&i| !self.rows[i].commutes(p))
+			.fold(Pauli::identity(n), |q, i| q.mul(&self.rows[i + n]));
+		product == *p
+	}
+
+	// Compare stabilizer groups, which only determine a state up to global phase
+	// (so `==` on stabilizer states ignores phases like `@[1/2]`, unlike on state vectors)
+	pub fn same_state(&self, t: &Tableau) -> bool {
+		self.qubits == t.qubits && t.stabilizers().iter().all(|p| self.stabilizes(p))
+	}
+
+	// Project a basis state in the support onto the stabilizer group
+	pub fn to_state(&self) -> State {
+		let n = self.qubits;
+		let mut support = self.clone();
+		let k = (0..n).fold(0, |k, a| (k << 1) | support.measure_qubit(a, || false) as usize);
+		let mut s = vec![Cf::zero(); 1 << n];
+		s[k] = Cf::one();
+		for p in self.stabilizers() {
+			let mut next = s.clone();
+			for (b, &x) in s.iter().enumerate() {
+				if !x.is_zero() {
+					let (target, n) = p.apply_basis(b);
+					next[target] += n * x;
+				}
+			}
+			s = next;
+		}
+		s.normalized()
+	}
+}
+
+impl fmt::Display for Tableau {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "stabilizer[{}]", self.stabilizers().iter().map(|p| format!("{}", p)).collect::<Vec<_>>().join(", "))
+	}
+}
+
@@ -1,9 +1,13 @@
 use error::*;
 use parser::parse;
-use ast::Exp;
+use ast::{Exp, Pat};
 use engine::*;
 use eval::*;
 use types::*;
+use optimize::*;
+use backend::*;
+
+use std::cmp::Ordering;
 
 pub fn create_ctx(path: &str) -> Ret<Context> {
 	let mut ctx = Context::new(path.to_string());
@@ -12,19 +16,69 @@ pub fn create_ctx(path: &str) -> Ret<Context> {
 	ctx.add_macro("phf", &lib_phf)?;
 	ctx.add_macro("gate", &lib_gate)?;
 	ctx.add_macro("inv", &lib_inv)?;
+	ctx.add_macro("expm", &lib_expm)?;
+	ctx.add_macro("logm", &lib_logm)?;
+	ctx.add_macro("evolve", &lib_evolve)?;
+	ctx.add_macro("is_unitary", &lib_is_unitary)?;
+	ctx.add_macro("unitarity", &lib_unitarity)?;
 	ctx.add_macro("len", &lib_len)?;
 	ctx.add_macro("slice", &lib_slice)?;
 	ctx.add_macro("weighted", &lib_weighted)?;
+	ctx.add_macro("amp", &lib_amp)?;
+	ctx.add_macro("normalize", &lib_normalize)?;
 	ctx.add_macro("fourier", &lib_fourier)?;
 	ctx.add_macro("repeat", &lib_repeat)?;
 	ctx.add_macro("fold", &lib_fold)?;
 	ctx.add_macro("measure", &lib_measure)?;
+	ctx.add_macro("measure_part", &lib_measure_part)?;
+	ctx.add_macro("truncation", &lib_truncation)?;
+	ctx.add_macro("pauli", &lib_pauli)?;
+	ctx.add_macro("expect", &lib_expect)?;
+	ctx.add_macro("grad", &lib_grad)?;
+	ctx.add_macro("param_shift", &lib_param_shift)?;
+	ctx.add_macro("minimize", &lib_minimize)?;
+	ctx.add_macro("add", &lib_add)?;
+	ctx.add_macro("sub", &lib_sub)?;
+	ctx.add_macro("mul", &lib_mul)?;
+	ctx.add_macro("div", &lib_div)?;
+	ctx.add_macro("mod", &lib_mod)?;
+	ctx.add_macro("eq", &lib_eq)?;
+	ctx.add_macro("ne", &lib_ne)?;
+	ctx.add_macro("lt", &lib_lt)?;
+	ctx.add_macro("gt", &lib_gt)?;
+	ctx.add_macro("le", &lib_le)?;
+	ctx.add_macro("ge", &lib_ge)?;
+	ctx.add_macro("and", &lib_and)?;
+	ctx.add_macro("or", &lib_or)?;
+	ctx.add_macro("xor", &lib_xor)?;
+	ctx.add_macro("not", &lib_not)?;
+	ctx.add_macro("trace_out", &lib_trace_out)?;
+	ctx.add_macro("reduced", &lib_reduced)?;
+	ctx.add_macro("schmidt", &lib_schmidt)?;
+	ctx.add_macro("entropy", &lib_entropy)?;
+	ctx.add_macro("purity", &lib_purity)?;
+	ctx.add_macro("concurrence", &lib_concurrence)?;
+	ctx.add_macro("fidelity", &lib_fidelity)?;
+	ctx.add_macro("overlap", &lib_overlap)?;
+	ctx.add_macro("trace_distance", &lib_trace_distance)?;
+	ctx.add_macro("sample", &lib_sample)?;
+	ctx.add_macro("seed", &lib_seed)?;
+	ctx.add_macro("probs", &lib_probs)?;
+	ctx.add_macro("density", &lib_density)?;
+	ctx.add_macro("kraus", &lib_kraus)?;
+	ctx.add_macro("bit_flip", &lib_bit_flip)?;
+	ctx.add_macro("depolarize", &lib_depolarize)?;
+	ctx.add_macro("amp_damp", &lib_amp_damp)?;
+	ctx.add_macro("phase_damp", &lib_phase_damp)?;
 	ctx.add_macro("typeof", &lib_typeof)?;
 	eval_exp_inline(&parse(r#"
 		data Bool = F | T
 		data Axis = X | Y | Z
 		
 		let ((^), (~), (#)) = (sup, phf, measure)
+		let ((+), (-), (*), (/), (%)) = (add, sub, mul, div, mod)
+		let ((==), (!=), (<), (>), (<=), (>=)) = (eq, ne, lt, gt, le, ge)
+		let ((&), (|), (!)) = (and, or, not)
 		fn identity(a) = a
 		fn (>>)(x, f) = f(x)
 		fn (<<)(f, x) = f(x)
@@ -72,39 +126,80 @@ fn lib_import(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 }
 
 fn lib_sup(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
-	Ok(RunVal::State(match eval_exp(exp, ctx) {
-		RunVal::Tuple(args) => create_sup(args.into_iter().map(build_state).collect()),
-		val => build_state(val),
-	}, Type::Any /* TODO infer from arg types */))
+	match eval_exp(exp, ctx) {
+		RunVal::Tuple(args) => ctx.backend().sup(args),
+		val => ctx.backend().sup(vec![val]),
+	}
 }
 
 fn lib_phf(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 	let val = eval_exp(exp, ctx);
-	let gate = build_gate(&val, ctx)
-		.map(|g| RunVal::Gate(g.negate()));
-	if let Some(g) = gate {Ok(g)}
-	else {
-		let (s, t) = build_state_typed(val)?;
-		Ok(RunVal::State(s.phase_flip(), t))
+	match build_gate(&val) {
+		Some(g) => Ok(RunVal::Gate(g.negate())),
+		// Flip the sign of a state with the phase (-1)^1
+		None => ctx.backend().phase(val, Cf::new(1.0, 0.0)),
 	}
 }
 
 fn lib_gate(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 	let val = eval_exp(exp, ctx);
-	Ok(RunVal::Gate(build_gate(&val, ctx).ok_or_else(|| Error(format!("Not a gate: {}", val)))?))
+	Ok(RunVal::Gate(build_gate(&val).ok_or_else(|| Error(format!("Not a gate: {}", val)))?))
 }
 
 fn lib_inv(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 	let val = eval_exp(exp, ctx);
-	Ok(RunVal::Gate(build_gate(&val, ctx).ok_or_else(|| Error(format!("Not a gate: {}", val)))?
+	Ok(RunVal::Gate(build_gate(&val).ok_or_else(|| Error(format!("Not a gate: {}", val)))?
 		.inverse()))
 }
 
+fn lib_expm(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let val = eval_exp(exp, ctx);
+	Ok(RunVal::Gate(build_gate(&val).ok_or_else(|| Error(format!("Not a gate: {}", val)))?
+		.expm()?))
+}
+
+fn lib_logm(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let val = eval_exp(exp, ctx);
+	Ok(RunVal::Gate(build_gate(&val).ok_or_else(|| Error(format!("Not a gate: {}", val)))?
+		.logm()?))
+}
+
+// Time evolution e^(-iHt) under a Hermitian gate `H`
+fn lib_evolve(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let val = eval_exp(&args[0], ctx);
+			let h = build_gate(&val).ok_or_else(|| Error(format!("Not a gate: {}", val)))?.to_gate();
+			if !is_hermitian(&h) {
+				return err!("Not a Hermitian gate: {}", val)
+			}
+			let t = build_real(&eval_exp(&args[1], ctx))?;
+			let scaled = h.into_iter().map(|s| s.into_iter().map(|n| n * Cf::new(0.0, -t)).collect()).collect();
+			Ok(RunVal::Gate(Op::from_gate(scaled).expm()?))
+		},
+		_ => err!("Invalid `evolve` arguments"),
+	}
+}
+
+fn lib_is_unitary(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let val = eval_exp(exp, ctx);
+	let gate = build_gate(&val).ok_or_else(|| Error(format!("Not a gate: {}", val)))?;
+	build_bool_val(gate.is_unitary(), ctx)
+}
+
+// Classification of a gate as "unitary", "isometric" (preserving norms but widening the state) or "non-unitary"
+fn lib_unitarity(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let val = eval_exp(exp, ctx);
+	let gate = build_gate(&val).ok_or_else(|| Error(format!("Not a gate: {}", val)))?;
+	Ok(RunVal::String(gate.unitarity().to_string()))
+}
+
 fn lib_len(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 	let val = eval_exp(exp, ctx);
-	Ok(RunVal::Index(build_gate(&val, ctx)
-		.map(|g| g.len())
-		.unwrap_or_else(|| build_state(val).len())))
+	match build_gate(&val) {
+		Some(g) => Ok(RunVal::Index(g.len())),
+		None => Ok(RunVal::Index(state_size(&val)?)),
+	}
 }
 
 fn lib_slice(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
@@ -136,26 +231,56 @@ fn lib_weighted(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 		Exp::Tuple(ref args) => {
 			let weights: State = args.iter().map(|arg| {
 				let val = eval_exp(arg, ctx);
-				if let RunVal::Index(n) = val {Ok(Cf32::new(n as f32, 0_f32))}
+				if let RunVal::Index(n) = val {Ok(Cf::new(n as Real, 0_f64))}
 				else {err!("Invalid weight: {}", val)}
 			}).collect::<Ret<_>>()?;
-			let div = weights.iter().fold(Cf32::new(0_f32, 0_f32), |a, b| a + b).sqrt();
+			let div = weights.iter().fold(Cf::new(0_f64, 0_f64), |a, b| a + b).sqrt();
 			Ok(RunVal::State(weights.into_iter().map(|w| w.sqrt() / div).collect(), Type::Any))
 		},
 		_ => err!("Invalid `weighted` arguments"),
 	}
 }
 
+// State with the given amplitudes, e.g. `amp(0.6 => F, 0.8i => T)` (normalized automatically)
+fn lib_amp(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if !args.is_empty() => {
+			let terms = args.iter().map(|arg| match eval_exp(arg, ctx) {
+				RunVal::Tuple(mut pair) if pair.len() == 2 => {
+					let (s, t) = build_state_typed(pair.pop().unwrap())?;
+					Ok((build_number(&pair[0])?, s, t))
+				},
+				val => err!("Invalid amplitude: {}", val),
+			}).collect::<Ret<Vec<_>>>()?;
+			let t = terms[0].2.clone();
+			normalize_state(create_amp(terms.into_iter().map(|(w, s, _)| (w, s)).collect()), t)
+		},
+		_ => err!("Invalid `amp` arguments"),
+	}
+}
+
+fn lib_normalize(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (s, t) = build_state_typed(eval_exp(exp, ctx))?;
+	normalize_state(s, t)
+}
+
+fn normalize_state(s: State, t: Type) -> Ret<RunVal> {
+	if s.prob_sum() < EPSILON {
+		return err!("Cannot normalize a zero state")
+	}
+	Ok(RunVal::State(s.normalized(), t))
+}
+
 fn lib_fourier(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 	match eval_exp(exp, ctx) {
 		RunVal::Index(n) if n > 0 => {
-			let w = (-2_f32 * ::std::f32::consts::PI * Cf32::i() / n as f32).exp();
-			let div = (n as f32).sqrt();
-			Ok(RunVal::Gate((0..n)
+			let w = (-2_f64 * ::std::f64::consts::PI * Cf::i() / n as Real).exp();
+			let div = (n as Real).sqrt();
+			Ok(RunVal::Gate(Op::Dense((0..n)
 				.map(|i| (0..n)
-					.map(|j| w.powc(Cf32::new((i * j) as f32, 0_f32)) / div)
+					.map(|j| w.powc(Cf::new((i * j) as Real, 0_f64)) / div)
 					.collect())
-				.collect()))
+				.collect())))
 		},
 		val => err!("Invalid size argument: {}", val),
 	}
@@ -163,7 +288,7 @@ fn lib_fourier(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 
 fn lib_repeat(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 	fn do_repeat(state: State, n: usize) -> State {
-		let div = (n as f32).sqrt();
+		let div = (n as Real).sqrt();
 		(0..n).flat_map(|_| state.iter().map(|s| s / div)).collect()
 	}
 	match exp {
@@ -171,9 +296,9 @@ fn lib_repeat(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 			let val = eval_exp(&args[0], ctx);
 			match eval_exp(&args[1], ctx) {
 				RunVal::Index(n) => {
-					if let Some(gate) = build_gate(&val, ctx) {
-						let wide = gate.into_iter().map(|v| do_repeat(v, n)).collect();
-						Ok(RunVal::Gate(::std::iter::repeat(wide).take(n).flat_map(|g: Gate| g).collect()))
+					if let Some(gate) = build_gate(&val) {
+						let wide: Gate = gate.to_gate().into_iter().map(|v| do_repeat(v, n)).collect();
+						Ok(RunVal::Gate(Op::from_gate(vec![wide; n].concat())))
 					}
 					else {Ok(RunVal::State(do_repeat(build_state(val), n), Type::Any))}
 				},
@@ -209,10 +334,547 @@ fn lib_fold(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 }
 
 fn lib_measure(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
-	let (s, t) = build_state_typed(eval_exp(exp, ctx))?;
-	t.assign(RunVal::Index(s.measure()))
+	ctx.backend().measure(eval_exp(exp, ctx), ctx.config())
+}
+
+// Weight discarded by bond truncation of a matrix product state (other states are exact)
+fn lib_truncation(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match eval_exp(exp, ctx) {
+		RunVal::Mps(mps, _) => Ok(RunVal::Real(mps.truncation)),
+		RunVal::State(_, _) | RunVal::Sparse(_, _) | RunVal::Density(_, _) | RunVal::Stabilizer(_, _) | RunVal::Data(_, _) | RunVal::Tuple(_) => Ok(RunVal::Real(0.0)),
+		val => err!("Not a state: {}", val),
+	}
+}
+
+fn lib_pauli(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	Ok(RunVal::Observable(build_observable(&eval_exp(exp, ctx))?))
+}
+
+// Expectation value of an observable (Pauli strings or a gate) for a pure or mixed state
+fn lib_expect(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let obs = eval_exp(&args[0], ctx);
+			let val = eval_exp(&args[1], ctx);
+			let n = match build_observable(&obs) {
+				Ok(obs) => Cf::new(match val {
+					RunVal::Stabilizer(ref t, _) => obs.expect_tableau(t)?,
+					ref val if is_mixed(val) => obs.expect_density(&build_density_typed(val.clone())?.0)?,
+					val => obs.expect_state(&build_state_typed(val)?.0)?,
+				}, 0.0),
+				Err(e) => {
+					let g = build_gate(&obs).ok_or(e)?.to_gate();
+					if is_mixed(&val) {
+						// Tr(ρO), where `g[i][j]` is entry (j, i) of the operator
+						let (rho, _) = build_density_typed(val)?;
+						if rho.size() != g.len() {
+							return err!("Observable of size {} applied to a {}-dimensional state", g.len(), rho.size())
+						}
+						rho.0.iter().zip(g.iter()).fold(Cf::new(0.0, 0.0), |sum, (r, c)| r.iter().zip(c).fold(sum, |sum, (a, b)| sum + a * b))
+					}
+					else {
+						let (s, _) = build_state_typed(val)?;
+						if s.len() != g.len() {
+							return err!("Observable of size {} applied to a {}-dimensional state", g.len(), s.len())
+						}
+						overlap(&s, &s.clone().extract(g))
+					}
+				},
+			};
+			Ok(if n.im.abs() < EPSILON {RunVal::Real(n.re)} else {RunVal::Complex(n)})
+		},
+		_ => err!("Invalid `expect` arguments"),
+	}
+}
+
+// Parameters of a function given as a number or a tuple of numbers (returned along with whether they are a single number).
+// A function of one parameter reads a fraction `(n, d)` as a single number.
+fn build_params(val: &RunVal, func: &RunVal) -> Ret<(Vec<Real>, bool)> {
+	fn takes_tuple(pat: &Pat) -> bool {
+		match pat {
+			&Pat::Tuple(_) | &Pat::Concat(_) | &Pat::Repeat(_, _) => true,
+			&Pat::Anno(ref pat, _) => takes_tuple(pat),
+			&Pat::Any | &Pat::Var(_) => false,
+		}
+	}
+	match (val, func) {
+		(&RunVal::Tuple(ref vals), &RunVal::Func(_, ref pat, _, _)) if takes_tuple(pat) => Ok((vals.iter().map(build_real).collect::<Ret<_>>()?, false)),
+		(val, _) => Ok((vec![build_real(val)?], true)),
+	}
+}
+
+fn params_val(params: Vec<Real>, single: bool) -> RunVal {
+	if single {RunVal::Real(params[0])}
+	else {RunVal::Tuple(params.into_iter().map(RunVal::Real).collect())}
+}
+
+// Evaluate a real-valued function of the given parameters
+fn call_objective(func: &RunVal, params: &[Real], single: bool, ctx: &Context) -> Ret<Real> {
+	if let &RunVal::Func(ref fn_ctx_rc, ref pat, ref body, _) = func {
+		let mut fn_ctx = (**fn_ctx_rc).clone();
+		*fn_ctx.config_mut() = ctx.config().clone();
+		assign_pat(pat, &params_val(params.to_vec(), single), &mut fn_ctx)?;
+		let val = eval_exp(body, &fn_ctx);
+		build_real(&val).map_err(|_| Error(format!("Objective must return a real number: {}", val)))
+	}
+	else {
+		err!("Not a function: {}", func)
+	}
+}
+
+// Gradient by central finite differences, with an optional step size as the third argument
+fn lib_grad(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 || args.len() == 3 => {
+			let func = eval_exp(&args[0], ctx);
+			let (params, single) = build_params(&eval_exp(&args[1], ctx), &func)?;
+			let step = match args.get(2) {
+				Some(step) => build_real(&eval_exp(step, ctx))?,
+				None => default_step(),
+			};
+			let grad = finite_difference(|x| call_objective(&func, x, single, ctx), &params, step)?;
+			Ok(params_val(grad, single))
+		},
+		_ => err!("Invalid `grad` arguments"),
+	}
+}
+
+// Gradient by the parameter-shift rule, which is exact only if each parameter `θ` appears once as `@[θ] g` for a gate with `g(g(x)) == x`
+// (otherwise the result is silently wrong, so `grad` is the safe default)
+fn lib_param_shift(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let func = eval_exp(&args[0], ctx);
+			let (params, single) = build_params(&eval_exp(&args[1], ctx), &func)?;
+			let grad = parameter_shift(|x| call_objective(&func, x, single, ctx), &params)?;
+			Ok(params_val(grad, single))
+		},
+		_ => err!("Invalid `param_shift` arguments"),
+	}
+}
+
+// Minimize a real-valued function from the given starting parameters, returning `(params, value)`
+fn lib_minimize(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let func = eval_exp(&args[0], ctx);
+			let (params, single) = build_params(&eval_exp(&args[1], ctx), &func)?;
+			let (params, value) = nelder_mead(|x| call_objective(&func, x, single, ctx), &params)?;
+			Ok(RunVal::Tuple(vec![params_val(params, single), RunVal::Real(value)]))
+		},
+		_ => err!("Invalid `minimize` arguments"),
+	}
+}
+
+fn build_number(val: &RunVal) -> Ret<Cf> {
+	build_complex(val).ok_or_else(|| Error(format!("Not a number: {}", val)))
+}
+
+// Apply a numeric operator, giving a complex result if either operand is complex and otherwise a real result
+fn numeric_op(a: &RunVal, b: &RunVal, f: &dyn Fn(Cf, Cf) -> Cf) -> Ret<RunVal> {
+	let n = f(build_number(a)?, build_number(b)?);
+	Ok(match (a, b) {
+		(&RunVal::Complex(_), _) | (_, &RunVal::Complex(_)) => RunVal::Complex(n),
+		_ => RunVal::Real(n.re),
+	})
+}
+
+// Linear combination `a + w * b` of states (which are not normalized)
+fn combine_states(a: RunVal, b: RunVal, w: Cf) -> Ret<RunVal> {
+	let (a, t) = build_state_typed(a)?;
+	let (b, _) = build_state_typed(b)?;
+	Ok(RunVal::State(create_amp(vec![(Cf::new(1.0, 0.0), a), (w, b)]), t))
+}
+
+// Operands of a binary operator (which may be passed as a tuple value, e.g. through `fn (+)`)
+fn binary_args(val: RunVal, name: &str) -> Ret<(RunVal, RunVal)> {
+	match val {
+		RunVal::Tuple(mut args) if args.len() == 2 => {
+			let b = args.pop().unwrap();
+			Ok((args.pop().unwrap(), b))
+		},
+		_ => err!("Invalid `{}` arguments", name),
+	}
+}
+
+// Sum of numbers (indices stay indices), observables or states
+fn lib_add(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (a, b) = binary_args(eval_exp(exp, ctx), "add")?;
+	match (&a, &b) {
+		(&RunVal::Index(x), &RunVal::Index(y)) => x.checked_add(y).map(RunVal::Index).ok_or_else(|| Error(format!("Index overflow: {} + {}", x, y))),
+		(&RunVal::Observable(_), _) | (_, &RunVal::Observable(_)) => Ok(RunVal::Observable(build_observable(&a)?.plus(build_observable(&b)?)?)),
+		(&RunVal::State(_, _), _) | (_, &RunVal::State(_, _)) => combine_states(a, b, Cf::new(1.0, 0.0)),
+		_ => numeric_op(&a, &b, &|x, y| x + y),
+	}
+}
+
+// Subtraction (of indices, as long as the result is not negative), or negation given a single argument
+fn lib_sub(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match eval_exp(exp, ctx) {
+		val @ RunVal::Tuple(_) => {
+			let (a, b) = binary_args(val, "sub")?;
+			match (&a, &b) {
+				(&RunVal::Index(x), &RunVal::Index(y)) => x.checked_sub(y).map(RunVal::Index).ok_or_else(|| Error(format!("Index underflow: {} - {}", x, y))),
+				(&RunVal::Observable(_), _) | (_, &RunVal::Observable(_)) => Ok(RunVal::Observable(build_observable(&a)?.plus(build_observable(&b)?.scale(-1.0))?)),
+				(&RunVal::State(_, _), _) | (_, &RunVal::State(_, _)) => combine_states(a, b, Cf::new(-1.0, 0.0)),
+				_ => numeric_op(&a, &b, &|x, y| x - y),
+			}
+		},
+		RunVal::Index(n) => Ok(RunVal::Real(-(n as Real))),
+		RunVal::Real(n) => Ok(RunVal::Real(-n)),
+		RunVal::Complex(n) => Ok(RunVal::Complex(-n)),
+		RunVal::Observable(obs) => Ok(RunVal::Observable(obs.scale(-1.0))),
+		RunVal::State(s, t) => Ok(RunVal::State(s.phase_flip(), t)),
+		val => err!("Not a number: {}", val),
+	}
+}
+
+// Product of numbers, or an observable scaled by a real number
+fn lib_mul(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (a, b) = binary_args(eval_exp(exp, ctx), "mul")?;
+	match (a, b) {
+		(RunVal::Index(x), RunVal::Index(y)) => x.checked_mul(y).map(RunVal::Index).ok_or_else(|| Error(format!("Index overflow: {} * {}", x, y))),
+		(RunVal::Observable(obs), w) | (w, RunVal::Observable(obs)) => Ok(RunVal::Observable(obs.scale(build_real(&w)?))),
+		(RunVal::State(s, t), w) | (w, RunVal::State(s, t)) => {
+			let w = build_number(&w)?;
+			Ok(RunVal::State(s.into_iter().map(|x| x * w).collect(), t))
+		},
+		(a, b) => numeric_op(&a, &b, &|x, y| x * y),
+	}
+}
+
+// Quotient of numbers (always real or complex, even for indices)
+fn lib_div(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (a, b) = binary_args(eval_exp(exp, ctx), "div")?;
+	if build_complex(&b) == Some(Cf::new(0.0, 0.0)) {
+		return err!("Division by zero: {} / {}", a, b)
+	}
+	match (a, b) {
+		(RunVal::Observable(obs), b) => Ok(RunVal::Observable(obs.scale(1.0 / build_real(&b)?))),
+		(RunVal::State(s, t), b) => {
+			let w = build_number(&b)?;
+			Ok(RunVal::State(s.into_iter().map(|x| x / w).collect(), t))
+		},
+		(a, b) => numeric_op(&a, &b, &|x, y| x / y),
+	}
+}
+
+fn lib_mod(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match binary_args(eval_exp(exp, ctx), "mod")? {
+		(a, RunVal::Index(0)) => err!("Division by zero: {} % 0", a),
+		(RunVal::Index(x), RunVal::Index(y)) => Ok(RunVal::Index(x % y)),
+		(a, b) => {
+			let (x, y) = (build_real(&a)?, build_real(&b)?);
+			if y == 0.0 {
+				return err!("Division by zero: {} % {}", a, b)
+			}
+			Ok(RunVal::Real(x - y * (x / y).floor()))
+		},
+	}
+}
+
+fn lib_eq(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (a, b) = binary_args(eval_exp(exp, ctx), "eq")?;
+	build_bool_val(values_eq(&a, &b)?, ctx)
+}
+
+fn lib_ne(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (a, b) = binary_args(eval_exp(exp, ctx), "ne")?;
+	build_bool_val(!values_eq(&a, &b)?, ctx)
+}
+
+// Order two real numbers
+fn compare_args(exp: &Exp, ctx: &Context, name: &str) -> Ret<Ordering> {
+	let (a, b) = binary_args(eval_exp(exp, ctx), name)?;
+	match (&a, &b) {
+		(&RunVal::Index(x), &RunVal::Index(y)) => Ok(x.cmp(&y)),
+		_ => build_real(&a)?.partial_cmp(&build_real(&b)?).ok_or_else(|| Error(format!("Cannot compare {} and {}", a, b))),
+	}
+}
+
+fn lib_lt(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	build_bool_val(compare_args(exp, ctx, "lt")? == Ordering::Less, ctx)
+}
+
+fn lib_gt(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	build_bool_val(compare_args(exp, ctx, "gt")? == Ordering::Greater, ctx)
+}
+
+fn lib_le(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	build_bool_val(compare_args(exp, ctx, "le")? != Ordering::Greater, ctx)
+}
+
+fn lib_ge(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	build_bool_val(compare_args(exp, ctx, "ge")? != Ordering::Less, ctx)
+}
+
+// Bitwise operator on indices, or the corresponding logical operator on `Bool` values
+fn bitwise_op(exp: &Exp, ctx: &Context, name: &str, f: &dyn Fn(usize, usize) -> usize) -> Ret<RunVal> {
+	let bool_type = ctx.find_type(&"Bool".to_string())?;
+	match binary_args(eval_exp(exp, ctx), name)? {
+		(RunVal::Index(x), RunVal::Index(y)) => Ok(RunVal::Index(f(x, y))),
+		(RunVal::Data(ref a, x), RunVal::Data(ref b, y)) if Type::Data(a.clone()) == bool_type && Type::Data(b.clone()) == bool_type => build_bool_val(f(x, y) > 0, ctx),
+		(a, b) => err!("Invalid `{}` arguments: {} and {}", name, a, b),
+	}
+}
+
+fn lib_and(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	bitwise_op(exp, ctx, "and", &|x, y| x & y)
+}
+
+fn lib_or(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	bitwise_op(exp, ctx, "or", &|x, y| x | y)
+}
+
+fn lib_xor(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	bitwise_op(exp, ctx, "xor", &|x, y| x ^ y)
+}
+
+fn lib_not(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match eval_exp(exp, ctx) {
+		val @ RunVal::Data(_, _) | val @ RunVal::Index(_) | val @ RunVal::Tuple(_) => build_bool_val(!build_bool(&val).unwrap(), ctx),
+		val => err!("Invalid `not` argument: {}", val),
+	}
+}
+
+// Measure one component of a tuple-typed state as `(outcome, remaining state)`
+fn lib_measure_part(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => match eval_exp(&args[1], ctx) {
+			RunVal::Index(k) => ctx.backend().measure_part(eval_exp(&args[0], ctx), k, ctx.config()),
+			val => err!("Invalid component index: {}", val),
+		},
+		_ => err!("Invalid arguments: {:?}", exp),
+	}
+}
+
+// Reduced state after discarding the given components of a tuple-typed state
+fn lib_trace_out(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let (rho, t) = trace_components(eval_exp(&args[0], ctx), &component_indices(eval_exp(&args[1], ctx))?)?;
+			Ok(RunVal::Density(rho, t))
+		},
+		_ => err!("Invalid arguments: {:?}", exp),
+	}
+}
+
+// Marginal state of one component of a tuple-typed state
+fn lib_reduced(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => match eval_exp(&args[1], ctx) {
+			RunVal::Index(k) => {
+				let (rho, t) = keep_components(eval_exp(&args[0], ctx), &[k])?;
+				Ok(RunVal::Density(rho, t))
+			},
+			val => err!("Invalid component index: {}", val),
+		},
+		_ => err!("Invalid arguments: {:?}", exp),
+	}
+}
+
+// Schmidt coefficients across the bipartition between the given components and the rest
+fn lib_schmidt(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let val = eval_exp(&args[0], ctx);
+			if is_mixed(&val) {
+				return err!("Schmidt decomposition requires a pure state: {}", val)
+			}
+			let (rho, _) = keep_components(val, &component_indices(eval_exp(&args[1], ctx))?)?;
+			let (vals, _) = ::linalg::eigh(&rho.0);
+			Ok(RunVal::Tuple(vals.into_iter().rev()
+				.filter(|&p| p > EPSILON * EPSILON)
+				.map(|p| RunVal::Real(p.sqrt()))
+				.collect()))
+		},
+		_ => err!("Invalid arguments: {:?}", exp),
+	}
+}
+
+// Von Neumann entropy (in bits)
+fn lib_entropy(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (rho, _) = build_density_typed(eval_exp(exp, ctx))?;
+	Ok(RunVal::Real(rho.entropy()))
+}
+
+fn lib_purity(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (rho, _) = build_density_typed(eval_exp(exp, ctx))?;
+	Ok(RunVal::Real(rho.purity()))
+}
+
+// Wootters concurrence of a two-qubit state
+fn lib_concurrence(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (rho, _) = build_density_typed(eval_exp(exp, ctx))?;
+	Ok(RunVal::Real(rho.concurrence()?))
+}
+
+// Inner product ⟨a|b⟩ of two pure states
+fn lib_overlap(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let (a, _) = build_state_typed(eval_exp(&args[0], ctx))?;
+			let (b, _) = build_state_typed(eval_exp(&args[1], ctx))?;
+			Ok(RunVal::Complex(overlap(&a, &b)))
+		},
+		_ => err!("Invalid arguments: {:?}", exp),
+	}
+}
+
+fn lib_fidelity(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let (a, b) = (eval_exp(&args[0], ctx), eval_exp(&args[1], ctx));
+			if is_mixed(&a) || is_mixed(&b) {
+				let (a, _) = build_density_typed(a)?;
+				let (b, _) = build_density_typed(b)?;
+				Ok(RunVal::Real(a.fidelity(&b)))
+			}
+			else {
+				let (a, _) = build_state_typed(a)?;
+				let (b, _) = build_state_typed(b)?;
+				Ok(RunVal::Real(overlap(&a, &b).norm_sqr()))
+			}
+		},
+		_ => err!("Invalid arguments: {:?}", exp),
+	}
+}
+
+fn lib_trace_distance(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let (a, _) = build_density_typed(eval_exp(&args[0], ctx))?;
+			let (b, _) = build_density_typed(eval_exp(&args[1], ctx))?;
+			Ok(RunVal::Real(a.trace_distance(&b)))
+		},
+		_ => err!("Invalid arguments: {:?}", exp),
+	}
+}
+
+fn component_indices(val: RunVal) -> Ret<Vec<usize>> {
+	match val {
+		RunVal::Index(k) => Ok(vec![k]),
+		RunVal::Tuple(vals) => vals.into_iter().map(|val| match val {
+			RunVal::Index(k) => Ok(k),
+			val => err!("Invalid component index: {}", val),
+		}).collect(),
+		val => err!("Invalid component index: {}", val),
+	}
+}
+
+fn trace_components(val: RunVal, traced: &[usize]) -> Ret<(Density, Type)> {
+	let (rho, t) = build_density_typed(val)?;
+	let (types, dims) = tuple_dims(&t, rho.size())?;
+	if let Some(&k) = traced.iter().find(|&&k| k >= types.len()) {
+		return err!("Cannot trace out component {} of type {}", k, t)
+	}
+	let kept = types.into_iter().enumerate().filter(|&(i, _)| !traced.contains(&i)).map(|(_, t)| t).collect::<Vec<_>>();
+	if kept.is_empty() {
+		return err!("Cannot trace out every component of type {}", t)
+	}
+	let rho = rho.pad(dims.iter().product());
+	Ok((partial_trace(&rho, &dims, traced), tuple_type(kept)))
+}
+
+fn keep_components(val: RunVal, kept: &[usize]) -> Ret<(Density, Type)> {
+	let n = match build_density_typed(val.clone())?.1 {
+		Type::Tuple(ref types) => types.len(),
+		t => return err!("Expected tuple-typed state: {}", t),
+	};
+	if let Some(&k) = kept.iter().find(|&&k| k >= n) {
+		return err!("Invalid component index: {}", k)
+	}
+	trace_components(val, &(0..n).filter(|i| !kept.contains(i)).collect::<Vec<_>>())
+}
+
+// Outcome distribution as `(outcome, probability)` pairs
+fn lib_probs(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (probs, t) = ctx.backend().probs(eval_exp(exp, ctx))?;
+	Ok(RunVal::Tuple(probs.into_iter().enumerate()
+		// Skip outcomes which only appear due to rounding error
+		.filter(|&(_, p)| p > EPSILON * EPSILON)
+		.map(|(i, p)| RunVal::Tuple(vec![outcome_val(&t, i), RunVal::Real(p)]))
+		.collect()))
+}
+
+// Histogram of repeated measurements as `(outcome, count)` pairs
+fn lib_sample(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match exp {
+		&Exp::Tuple(ref args) if args.len() == 2 => {
+			let (probs, t) = ctx.backend().probs(eval_exp(&args[0], ctx))?;
+			let shots = match eval_exp(&args[1], ctx) {
+				RunVal::Index(n) => n,
+				val => return err!("Invalid shot count: {}", val),
+			};
+			let mut counts = vec![0; probs.len()];
+			let mut rng = ctx.config().rng.get();
+			for _ in 0..shots {
+				counts[sample_index(&probs, &mut *rng)] += 1;
+			}
+			Ok(RunVal::Tuple(counts.into_iter().enumerate()
+				.filter(|&(_, n)| n > 0)
+				.map(|(i, n)| RunVal::Tuple(vec![outcome_val(&t, i), RunVal::Index(n)]))
+				.collect()))
+		},
+		_ => err!("Invalid `sample` arguments"),
+	}
+}
+
+
+fn lib_seed(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	match eval_exp(exp, ctx) {
+		RunVal::Index(n) => {
+			ctx.set_seed(n);
+			Ok(RunVal::Tuple(vec![]))
+		},
+		val => err!("Invalid seed: {}", val),
+	}
+}
+
+fn lib_density(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (rho, t) = build_density_typed(eval_exp(exp, ctx))?;
+	Ok(RunVal::Density(rho, t))
 }
 
 fn lib_typeof(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
 	Ok(RunVal::String(format!("{}", ::eval_static::infer_type(exp, ctx.types())?)))
 }
+
+fn lib_kraus(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let vals = match eval_exp(exp, ctx) {
+		RunVal::Tuple(vals) => vals,
+		val => vec![val],
+	};
+	let kraus = vals.iter()
+		.map(|val| build_gate(val).map(|g| g.to_gate()).ok_or_else(|| Error(format!("Not a gate: {}", val))))
+		.collect::<Ret<Channel>>()?;
+	if !is_trace_preserving(&kraus) {
+		return err!("Kraus operators are not trace-preserving: {}", RunVal::Channel(kraus))
+	}
+	Ok(RunVal::Channel(kraus))
+}
+
+fn lib_bit_flip(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	Ok(RunVal::Channel(bit_flip_channel(build_prob(&eval_exp(exp, ctx))?)))
+}
+
+// Depolarizing channel on a qubit, or on a state of dimension `dim` given as `depolarize(p, dim)`
+// (with a real or fractional probability, since `depolarize(1, 4)` is the fraction 1/4)
+fn lib_depolarize(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	let (p, dim) = match eval_exp(exp, ctx) {
+		RunVal::Tuple(ref args) if args.len() == 2 && !matches!(args[0], RunVal::Index(_)) => match args[1] {
+			RunVal::Index(dim) if dim > 0 => (build_prob(&args[0])?, dim),
+			ref val => return err!("Invalid dimension: {}", val),
+		},
+		val => (build_prob(&val)?, 2),
+	};
+	Ok(RunVal::Channel(depolarizing_channel(p, dim)))
+}
+
+fn lib_amp_damp(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	Ok(RunVal::Channel(amplitude_damping_channel(build_prob(&eval_exp(exp, ctx))?)))
+}
+
+fn lib_phase_damp(exp: &Exp, ctx: &Context) -> Ret<RunVal> {
+	Ok(RunVal::Channel(phase_damping_channel(build_prob(&eval_exp(exp, ctx))?)))
+}
@@ -0,0 +1,161 @@
+use engine::*;
+
+use num::{Zero, One, ToPrimitive};
+use num::complex::Complex;
+
+// Classical reversible gate (`perm[i]` is the output for input `i`)
+pub type Perm = Vec<usize>;
+
+// Find the permutation performed by a gate which maps each input to exactly one output.
+// Only exact zeros and ones count, so that nearby gates keep all of their amplitudes.
+pub fn as_permutation(g: &Gate) -> Option<Perm> {
+	let n = g.len();
+	let mut seen = vec![false; n];
+	let mut perm = Vec::with_capacity(n);
+	for s in g.iter() {
+		if s.len() > n {
+			return None
+		}
+		let mut target = None;
+		for (j, x) in s.iter().enumerate() {
+			if x.is_zero() {
+				continue
+			}
+			if target.is_some() || !x.is_one() || seen[j] {
+				return None
+			}
+			target = Some(j);
+		}
+		let j = target?;
+		seen[j] = true;
+		perm.push(j);
+	}
+	Some(perm)
+}
+
+// Find the phase applied to each input of a gate whose off-diagonal entries are exactly zero
+pub fn as_diagonal(g: &Gate) -> Option<State> {
+	let n = g.len();
+	g.iter().enumerate().map(|(i, s)| {
+		if s.len() > n || s.iter().enumerate().any(|(j, x)| i != j && !x.is_zero()) {None}
+		else {Some(s.get(i).cloned().unwrap_or(Complex::zero()))}
+	}).collect()
+}
+
+pub fn perm_to_gate(perm: &Perm) -> Gate {
+	perm.iter().map(|&j| get_state(j).pad(perm.len())).collect()
+}
+
+pub fn diag_to_gate(diag: &State) -> Gate {
+	diag.iter().enumerate().map(|(i, &n)| {
+		let mut s = vec![Complex::zero(); diag.len()];
+		s[i] = n;
+		s
+	}).collect()
+}
+
+pub fn is_identity(perm: &Perm) -> bool {
+	perm.iter().enumerate().all(|(i, &j)| i == j)
+}
+
+pub fn invert_perm(perm: &Perm) -> Perm {
+	let mut inv = vec![0; perm.len()];
+	for (i, &j) in perm.iter().enumerate() {
+		inv[j] = i;
+	}
+	inv
+}
+
+// Apply `a` and then `b`
+pub fn compose_perms(a: &Perm, b: &Perm) -> Perm {
+	a.iter().map(|&j| b[j]).collect()
+}
+
+// Integer power of a permutation, taken separately on each cycle
+pub fn perm_power(perm: &Perm, n: Real) -> Perm {
+	let mut out = vec![0; perm.len()];
+	let mut seen = vec![false; perm.len()];
+	for start in 0..perm.len() {
+		if seen[start] {
+			continue
+		}
+		let mut cycle = vec![start];
+		seen[start] = true;
+		while perm[*cycle.last().unwrap()] != start {
+			let j = perm[*cycle.last().unwrap()];
+			seen[j] = true;
+			cycle.push(j);
+		}
+		// The remainder of an integral float is exact, even beyond the range of `i64`
+		let len = cycle.len() as f64;
+		let shift = ((n % len + len) % len).to_usize().unwrap_or(0);
+		for (k, &i) in cycle.iter().enumerate() {
+			out[i] = cycle[(k + shift) % cycle.len()];
+		}
+	}
+	out
+}
+
+// Kronecker product of two permutations
+pub fn combine_perms(a: &Perm, b: &Perm) -> Perm {
+	a.iter().flat_map(|&i| b.iter().map(move |&j| i * b.len() + j)).collect()
+}
+
+pub fn permute_state(s: State, perm: &Perm) -> State {
+	let mut out = vec![Complex::zero(); perm.len()];
+	for (x, &j) in s.into_iter().zip(perm) {
+		out[j] = x;
+	}
+	out
+}
+
+// ρ -> PρP†
+pub fn permute_density(rho: Density, perm: &Perm) -> Density {
+	let mut out = vec![vec![Complex::zero(); perm.len()]; perm.len()];
+	for (row, &a) in rho.0.into_iter().zip(perm) {
+		for (n, &b) in row.into_iter().zip(perm) {
+			out[a][b] = n;
+		}
+	}
+	Density(out)
+}
+
+pub fn scale_state(s: State, diag: &State) -> State {
+	s.into_iter().chain(::std::iter::repeat(Complex::zero())).zip(diag).map(|(x, &d)| x * d).collect()
+}
+
+// ρ -> DρD†
+pub fn scale_density(rho: Density, diag: &State) -> Density {
+	let rho = rho.pad(diag.len());
+	Density(rho.0.into_iter().zip(diag).map(|(row, &a)| {
+		row.into_iter().zip(diag).map(|(n, &b)| a * n * b.conj()).collect()
+	}).collect())
+}
+
+// Apply a permutation to axis `k` of a state (see `engine::apply_axis`)
+pub fn permute_axis(s: &mut State, dims: &[usize], k: usize, perm: &Perm) {
+	let d = dims[k];
+	let inner: usize = dims[k + 1..].iter().product();
+	let outer = s.len() / (d * inner);
+	let mut fiber = vec![Complex::zero(); d];
+	for o in 0..outer {
+		for r in 0..inner {
+			let base = o * d * inner + r;
+			for (m, x) in fiber.iter_mut().enumerate() {
+				*x = s[base + m * inner];
+			}
+			for (&x, &j) in fiber.iter().zip(perm) {
+				s[base + j * inner] = x;
+			}
+		}
+	}
+}
+
+// Apply a diagonal gate to axis `k` of a state
+pub fn scale_axis(s: &mut State, dims: &[usize], k: usize, diag: &State) {
+	let d = dims[k];
+	let inner: usize = dims[k + 1..].iter().product();
+	for (i, x) in s.iter_mut().enumerate() {
+		*x *= diag[(i / inner) % d];
+	}
+}
@@ -64,6 +64,31 @@ impl Type {
 				}
 				else {Ok(RunVal::State(state, self.clone()))}
 			},
+			(_, RunVal::Sparse(state, _)) => {
+				if self.size().map(|s| s != state.size).unwrap_or(false) {
+					err!("A state of size {} is not of type {}", state.size, self)
+				}
+				else {Ok(RunVal::Sparse(state, self.clone()))}
+			},
+			(_, RunVal::Stabilizer(tableau, _)) => {
+				if self.size().map(|s| Some(s) != 1_usize.checked_shl(tableau.qubits as u32)).unwrap_or(false) {
+					err!("A stabilizer state of {} qubits is not of type {}", tableau.qubits, self)
+				}
+				else {Ok(RunVal::Stabilizer(tableau, self.clone()))}
+			},
+			(_, RunVal::Mps(mps, _)) => {
+				let size = mps.dims().iter().product::<usize>();
+				if self.size().map(|s| s != size).unwrap_or(false) {
+					err!("A matrix product state of size {} is not of type {}", size, self)
+				}
+				else {Ok(RunVal::Mps(mps, self.clone()))}
+			},
+			(_, RunVal::Density(rho, _)) => {
+				if self.size().map(|s| s != rho.size()).unwrap_or(false) {
+					err!("A mixed state of size {} is not of type {}", rho.size(), self)
+				}
+				else {Ok(RunVal::Density(rho, self.clone()))}
+			},
 			(_, val) => err!("{} is not of type {}", val, self)
 		}
 	}
@@ -83,13 +108,15 @@ impl Type {
 			Type::Any => Ok(RunVal::Index(n)),
 			Type::Data(ref dt) => Ok(RunVal::Data(dt.clone(), n)),
 			Type::Tuple(ref types) => {
-				let mut total_size = 1;
+				// First component is the most significant (consistent with `eval::build_state_typed()`)
+				let mut n = n;
 				let mut vals = vec![];
-				for t in types {
+				for t in types.iter().rev() {
 					let size = t.size().ok_or_else(|| Error(format!("{} does not have a known size", t)))?;
-					vals.push(t.from_index((n / total_size) % size)?);
-					total_size *= size;
+					vals.push(t.from_index(n % size)?);
+					n /= size;
 				}
+				vals.reverse();
 				Ok(RunVal::Tuple(vals))
 			},
 			Type::Concat(_) => err!("No index structure {} for type {}", n, self),
@@ -1,4 +1,5 @@
 extern crate funqy;
+extern crate rand;
 use funqy::*;
 
 #[test]
@@ -7,3 +8,401 @@ fn test_parser() {
 	// println!("{:?}", exp);
 	println!("\n>> {}\n", ctx.import_eval("Test").expect("Could not import test script"));
 }
+
+#[test]
+fn test_density() {
+	let mut ctx = create_ctx("tests/scripts").unwrap();
+	ctx.config_mut().density = true;
+	println!("\n>> {}\n", ctx.import_eval("examples/Density").expect("Could not import density script"));
+}
+
+#[test]
+#[should_panic(expected = "Non-unitary extraction (cases: F, T, <no case for input 2>)")]
+fn test_strict() {
+	let mut ctx = create_ctx("tests/scripts").unwrap();
+	ctx.config_mut().strict = true;
+	ctx.import_eval("raw: fn g {F => T, T => T} g(2)").unwrap();
+}
+
+#[test]
+fn test_seed() {
+	let run = || {
+		let ctx = create_ctx("tests/scripts").unwrap();
+		ctx.set_seed(42);
+		ctx.import_eval("raw: (measure(sup(0, 1, 2, 3)), sample(F ^ T, 100))").unwrap()
+	};
+	assert_eq!(run(), run());
+
+	// Reseeding within an imported file leaves the generator of the importer alone
+	let after_import = |seed| {
+		let ctx = create_ctx("tests/scripts").unwrap();
+		ctx.set_seed(seed);
+		ctx.import_eval("examples/Random").unwrap();
+		ctx.import_eval("raw: sample(sup(0, 1, 2, 3), 100)").unwrap()
+	};
+	assert_eq!(after_import(42), after_import(42));
+	assert_ne!(after_import(42), after_import(43));
+}
+
+#[test]
+fn test_precision() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	// Fractional powers of a 16-dimensional Fourier transform compose back to the original gate
+	match ctx.import_eval("raw: let f = fourier(16) let r = @[1/3] f r(r(r(inv(f)(sup(2)))))").unwrap() {
+		eval::RunVal::State(s, _) => for (i, n) in s.iter().enumerate() {
+			assert!((n.norm() - if i == 2 {1_f64} else {0_f64}).abs() < 1e-12, "Amplitude {} of {:?}", i, s);
+		},
+		val => panic!("Expected state: {}", val),
+	}
+}
+
+#[test]
+fn test_sparse() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	match ctx.import_eval("raw: gate(8 py)").unwrap() {
+		eval::RunVal::Gate(engine::Op::Sparse(g)) => assert_eq!(g.nnz(), 256),
+		val => panic!("Expected sparse gate: {}", val),
+	}
+	match ctx.import_eval("raw: gate(3 had)").unwrap() {
+		eval::RunVal::Gate(engine::Op::Dense(g)) => assert_eq!(g.len(), 8),
+		val => panic!("Expected dense gate: {}", val),
+	}
+	// Large registers stay sparse, even when too large for a state vector
+	match ctx.import_eval("raw: (@[1/4] px, 29 ident)(30 F)").unwrap() {
+		eval::RunVal::Sparse(s, _) => {
+			assert_eq!(s.size, 1 << 30);
+			assert_eq!(s.nnz(), 2);
+		},
+		val => panic!("Expected sparse state: {}", val),
+	}
+	match ctx.import_eval("raw: (12 had)(12 F)").unwrap() {
+		eval::RunVal::State(s, _) => assert_eq!(s.len(), 4096),
+		val => panic!("Expected dense state: {}", val),
+	}
+}
+
+#[test]
+fn test_structured() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	match ctx.import_eval("raw: gate(10 px)").unwrap() {
+		eval::RunVal::Gate(engine::Op::Perm(p)) => assert_eq!(p.len(), 1024),
+		val => panic!("Expected permutation: {}", val),
+	}
+	match ctx.import_eval("raw: @[1/2] gate(10 pz)").unwrap() {
+		eval::RunVal::Gate(engine::Op::Diag(d)) => assert_eq!(d.len(), 1024),
+		val => panic!("Expected diagonal: {}", val),
+	}
+	match ctx.import_eval("raw: inv(gate(had, px))").unwrap() {
+		eval::RunVal::Gate(engine::Op::Dense(g)) => assert_eq!(g.len(), 4),
+		val => panic!("Expected dense gate: {}", val),
+	}
+	// Small amplitudes are kept rather than rounded into a permutation
+	match ctx.import_eval("raw: gate(@[0.00001] px)").unwrap() {
+		eval::RunVal::Gate(engine::Op::Dense(g)) => assert!(g[0][1].norm() > 0.0),
+		val => panic!("Expected dense gate: {}", val),
+	}
+	match ctx.import_eval("raw: fn cycle {0 => 1, 1 => 2, 2 => 0} @[-4] gate(cycle)").unwrap() {
+		eval::RunVal::Gate(engine::Op::Perm(p)) => assert_eq!(p, vec![2, 0, 1]),
+		val => panic!("Expected permutation: {}", val),
+	}
+}
+
+#[test]
+fn test_stabilizer() {
+	let mut ctx = create_ctx("tests/scripts").unwrap();
+	ctx.config_mut().stabilizer = stabilizer::StabilizerMode::Always;
+	match ctx.import_eval("raw: (had, ident)(F, F)").unwrap() {
+		eval::RunVal::Stabilizer(t, _) => assert_eq!(format!("{}", t), "stabilizer[+XI, +IZ]"),
+		val => panic!("Expected stabilizer state: {}", val),
+	}
+	// Gate functions extract from their bound argument
+	match ctx.import_eval("raw: fn flipped(x) = extract (px, ident)(x) {(F, F) => (F, F), (F, T) => (F, T), (T, F) => (T, F), (T, T) => (T, T)} flipped((ident, ident)(F, F))").unwrap() {
+		eval::RunVal::Stabilizer(t, _) => assert_eq!(format!("{}", t), "stabilizer[-ZI, +IZ]"),
+		val => panic!("Expected stabilizer state: {}", val),
+	}
+	// Imaginary phases scale the norm, which needs the state vector
+	match ctx.import_eval("raw: @[0.5i] (had, ident)(F, F)").unwrap() {
+		eval::RunVal::State(s, _) => assert!((s.iter().map(|n| n.norm_sqr()).sum::<f64>() - (-::std::f64::consts::PI).exp()).abs() < 1e-12),
+		val => panic!("Expected state vector: {}", val),
+	}
+	println!("\n>> {}\n", ctx.import_eval("examples/Stabilizer").expect("Could not import stabilizer script"));
+
+	assert_eq!(ctx.import_eval("raw: len((30 F) >> (30 had))").unwrap(), eval::RunVal::Index(1 << 30));
+
+	ctx.config_mut().stabilizer = stabilizer::StabilizerMode::Never;
+	match ctx.import_eval("raw: (had, ident)(F, F)").unwrap() {
+		eval::RunVal::State(s, _) => assert_eq!(s.len(), 4),
+		val => panic!("Expected state vector: {}", val),
+	}
+}
+
+#[test]
+#[should_panic(expected = "Cannot extract from 30 qubits without the stabilizer tableau")]
+fn test_stabilizer_extract() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: extract (30 F) >> (30 had) {_ => F}").unwrap();
+}
+
+#[test]
+#[should_panic(expected = "A stabilizer state of 3 qubits is not of type Bool")]
+fn test_stabilizer_type() {
+	let mut ctx = create_ctx("tests/scripts").unwrap();
+	ctx.config_mut().stabilizer = stabilizer::StabilizerMode::Always;
+	ctx.import_eval("raw: let x: Bool = (had, ident, ident)(F, F, F) x").unwrap();
+}
+
+#[test]
+fn test_mps() {
+	let mut ctx = create_ctx("tests/scripts").unwrap();
+	assert!(ctx.config_mut().set_bond_dim(0).is_err());
+	ctx.config_mut().set_bond_dim(4).unwrap();
+	println!("\n>> {}\n", ctx.import_eval("examples/Mps").expect("Could not import MPS script"));
+	assert_eq!(ctx.import_eval("raw: len((40 F) >> (40 had))").unwrap(), eval::RunVal::Index(1 << 40));
+
+	// A Bell pair needs a bond dimension of 2
+	ctx.config_mut().bond_dim = Some(1);
+	match ctx.import_eval("raw: fn cx {(F, F) => (F, F), (F, T) => (F, T), (T, F) => (T, T), (T, T) => (T, F)} (F, F) >> (had, ident) >> cx").unwrap() {
+		eval::RunVal::Mps(mps, _) => assert!((mps.truncation - 0.5).abs() < 1e-6),
+		val => panic!("Expected matrix product state: {}", val),
+	}
+}
+
+#[test]
+#[should_panic(expected = "A matrix product state of size 8 is not of type Bool")]
+fn test_mps_type() {
+	let mut ctx = create_ctx("tests/scripts").unwrap();
+	ctx.config_mut().set_bond_dim(2).unwrap();
+	ctx.import_eval("raw: let x: Bool = (had, ident, ident)(F, F, F) x").unwrap();
+}
+
+#[test]
+fn test_backend() {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use backend::*;
+	use engine::*;
+	use error::Ret;
+	use eval::RunVal;
+	use types::Type;
+
+	// Record each operation before delegating to another simulator
+	struct Logged<B>(B, Rc<RefCell<Vec<&'static str>>>);
+
+	impl<B: Backend> Backend for Logged<B> {
+		fn name(&self) -> &str {
+			"logged"
+		}
+		fn prepare(&self, val: RunVal) -> Ret<RunVal> {
+			self.1.borrow_mut().push("prepare");
+			self.0.prepare(val)
+		}
+		fn apply(&self, val: RunVal, gate: Op, ty: Type, config: &eval::Config) -> Ret<RunVal> {
+			self.1.borrow_mut().push("apply");
+			self.0.apply(val, gate, ty, config)
+		}
+		fn apply_tuple(&self, val: RunVal, ops: Vec<Op>, ty: Type, config: &eval::Config) -> Ret<RunVal> {
+			self.1.borrow_mut().push("apply_tuple");
+			self.0.apply_tuple(val, ops, ty, config)
+		}
+		fn apply_channel(&self, val: RunVal, kraus: &Channel, ty: Type) -> Ret<RunVal> {
+			self.1.borrow_mut().push("apply_channel");
+			self.0.apply_channel(val, kraus, ty)
+		}
+		fn sup(&self, vals: Vec<RunVal>) -> Ret<RunVal> {
+			self.1.borrow_mut().push("sup");
+			self.0.sup(vals)
+		}
+		fn phase(&self, val: RunVal, phase: Phase) -> Ret<RunVal> {
+			self.1.borrow_mut().push("phase");
+			self.0.phase(val, phase)
+		}
+		fn measure(&self, val: RunVal, config: &eval::Config) -> Ret<RunVal> {
+			self.1.borrow_mut().push("measure");
+			self.0.measure(val, config)
+		}
+		fn measure_part(&self, val: RunVal, k: usize, config: &eval::Config) -> Ret<RunVal> {
+			self.1.borrow_mut().push("measure_part");
+			self.0.measure_part(val, k, config)
+		}
+		fn probs(&self, val: RunVal) -> Ret<(Vec<Real>, Type)> {
+			self.1.borrow_mut().push("probs");
+			self.0.probs(val)
+		}
+	}
+
+	let log = Rc::new(RefCell::new(vec![]));
+	let mut ctx = create_ctx("tests/scripts").unwrap();
+	ctx.set_backend(Logged(DenseBackend, log.clone()));
+	assert_eq!(ctx.backend().name(), "logged");
+	ctx.import_eval("raw: measure(px(@[1/2] sup(F, T)))").unwrap();
+	assert_eq!(*log.borrow(), vec!["sup", "phase", "apply", "measure"]);
+	log.borrow_mut().clear();
+	ctx.import_eval("raw: (had, ident)(F, F)").unwrap();
+	ctx.import_eval("raw: bit_flip(1, 2)(F)").unwrap();
+	assert_eq!(*log.borrow(), vec!["apply_tuple", "apply_channel"]);
+
+	// Functions use the backend of their caller, even when defined before it was set
+	let mut ctx = create_ctx("tests/scripts").unwrap();
+	let flip = ctx.import_eval("raw: fn flip(x) = px(x) flip").unwrap();
+	ctx.add_var("flip".to_string(), flip, Type::Any).unwrap();
+	ctx.set_backend(Logged(DenseBackend, log.clone()));
+	log.borrow_mut().clear();
+	ctx.import_eval("raw: flip(F)").unwrap();
+	assert_eq!(*log.borrow(), vec!["apply"]);
+
+	// Stabilizer states and the measurement functions of the standard library also go through the backend
+	let mut ctx = create_ctx("tests/scripts").unwrap();
+	ctx.config_mut().stabilizer = stabilizer::StabilizerMode::Always;
+	ctx.set_backend(Logged(MpsBackend(StabilizerBackend(DenseBackend)), log.clone()));
+	log.borrow_mut().clear();
+	match ctx.import_eval("raw: let s = (had, ident)(F, F) let r = (phf(s), @[1/2] s, measure(s), measure_part(s, 0), probs(s), sample(s, 10)) r").unwrap() {
+		RunVal::Tuple(vals) => assert!(matches!(vals[0], RunVal::Stabilizer(_, _))),
+		val => panic!("Expected tuple: {}", val),
+	}
+	let count = |name| log.borrow().iter().filter(|&&n| n == name).count();
+	assert_eq!(count("apply_tuple"), 1);
+	assert_eq!(count("phase"), 2);
+	assert_eq!(count("measure"), 1);
+	assert_eq!(count("measure_part"), 1);
+	assert_eq!(count("probs"), 2);
+	assert_eq!(log.borrow().len(), 7);
+}
+
+#[test]
+fn test_svd() {
+	use engine::Cf;
+	// Singular values 1 and 1e-9, which `eigh(m m†)` would lose below the rounding error of 1
+	let (a, b) = (0.5_f64.sqrt(), 1e-9);
+	let m = vec![
+		vec![Cf::new(a, 0.0), Cf::new(a * b, 0.0)],
+		vec![Cf::new(a, 0.0), Cf::new(-a * b, 0.0)],
+	];
+	let (u, s, vh) = linalg::svd(&m);
+	assert_eq!(s.len(), 2);
+	assert!((s[0] - 1.0).abs() < 1e-12, "{:?}", s);
+	assert!((s[1] - 1e-9).abs() < 1e-18, "{:?}", s);
+	let scaled = u.iter().map(|row| row.iter().zip(s.iter()).map(|(x, &s)| x * s).collect()).collect();
+	for (row, expected) in linalg::mul(&scaled, &vh).iter().zip(m.iter()) {
+		for (x, y) in row.iter().zip(expected) {
+			assert!((x - y).norm() < 1e-15, "{} != {}", x, y);
+		}
+	}
+}
+
+#[test]
+#[should_panic(expected = "Not a Hermitian gate")]
+fn test_evolve() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: fn skew {F => F, T => F ^ T} evolve(skew, 1)").unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Conditional state cannot be 3-dimensional")]
+fn test_mixed_cond() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: if density(sup(0, 1, 2)) then F else T").unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Concurrence requires a two-qubit state, not one of size 2")]
+fn test_concurrence() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: concurrence(F)").unwrap();
+}
+
+#[test]
+fn test_grad() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	let grad = |exp: &str| match ctx.import_eval(&format!("raw: fn ry(t) = @[t] py fn f(a, b) = expect(pauli(\"ZZ\"), (ry(a), ry(b))(F, F)) {}", exp)).unwrap() {
+		eval::RunVal::Tuple(vals) => vals.into_iter().map(|val| match val {
+			eval::RunVal::Real(n) => n,
+			val => panic!("Expected number: {}", val),
+		}).collect::<Vec<_>>(),
+		val => panic!("Expected tuple: {}", val),
+	};
+	// d/da cos(πa) cos(πb) = -π sin(πa) cos(πb)
+	let (a, b) = (1_f64 / 3_f64, 1_f64 / 4_f64);
+	let expected = [
+		-std::f64::consts::PI * (std::f64::consts::PI * a).sin() * (std::f64::consts::PI * b).cos(),
+		-std::f64::consts::PI * (std::f64::consts::PI * a).cos() * (std::f64::consts::PI * b).sin(),
+	];
+	for (g, e) in grad("grad(f, ((1, 3), (1, 4)))").iter().zip(expected.iter()) {
+		assert!((g - e).abs() < 1e-3, "{} != {}", g, e);
+	}
+	for (g, e) in grad("param_shift(f, ((1, 3), (1, 4)))").iter().zip(expected.iter()) {
+		assert!((g - e).abs() < 1e-4, "{} != {}", g, e);
+	}
+	for (g, e) in grad("grad(f, ((1, 3), (1, 4)), (1, 100))").iter().zip(expected.iter()) {
+		assert!((g - e).abs() < 1e-2, "{} != {}", g, e);
+	}
+}
+
+#[test]
+#[should_panic(expected = "Division by zero")]
+fn test_arith() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: 1 / (2 - 2)").unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Index overflow")]
+fn test_index_overflow() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: 18446744073709551615 + 1").unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Index underflow")]
+fn test_index_underflow() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: 2 - 3").unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Channel on 2 dimensions applied to a 4-dimensional state")]
+fn test_channel_dimension() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: depolarize(1, 2)((F, T))").unwrap();
+}
+
+#[test]
+fn test_power() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	// Small off-diagonal entries are kept by both dense and sparse gates
+	ctx.import_eval("raw: assert (@[20000] (@[0.00001] px))(F) == (@[0.2] px)(F) within 0.001").unwrap();
+	match ctx.import_eval("raw: gate(@[0.00001] px, 5 ident)").unwrap() {
+		eval::RunVal::Gate(engine::Op::Sparse(_)) => {},
+		val => panic!("Expected sparse gate: {}", val),
+	}
+	ctx.import_eval("raw: assert (@[20000] gate(@[0.00001] px, 5 ident))(F, 5 F) == (@[0.2] px, 5 ident)(F, 5 F) within 0.001").unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Cannot take a non-positive power of a singular gate")]
+fn test_singular_power() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: fn collapse {F => T, T => T} @[-1/2] collapse").unwrap();
+}
+
+#[test]
+fn test_compare() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	let eval = |exp: &str| format!("{}", ctx.import_eval(&format!("raw: {}", exp)).unwrap());
+	assert_eq!(eval("2 < 3"), "T");
+	assert_eq!(eval("if 7 % 2 == 1 then 5 & 3 else 0"), "1");
+	assert_eq!(eval("(1, 2) != (1, 2)"), "F");
+}
+
+#[test]
+#[should_panic(expected = "Invalid `and` arguments: L and R")]
+fn test_logical_data() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: data Dir = L | R L & R").unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Cannot normalize a zero state")]
+fn test_amp() {
+	let ctx = create_ctx("tests/scripts").unwrap();
+	ctx.import_eval("raw: amp(1 => F, 1 => T, -1 => F, -1 => T)").unwrap();
+}